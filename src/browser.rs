@@ -0,0 +1,610 @@
+use crate::expand::{AutoScroll, Expansion, Interaction};
+use crate::fetcher::{Asset, Conditional, Fetcher, HttpFetcher, Validators};
+use crate::politeness::is_retryable;
+use crate::url_reader::Crawler;
+use crate::wait::{PageReady, WaitStrategy};
+use async_trait::async_trait;
+use fantoccini::cookies::Cookie;
+use fantoccini::{Client, ClientBuilder};
+use serde_json::Value;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+use url::Url;
+
+struct GeckoDriver {
+    process: Child,
+}
+
+/// First WebDriver port; the session for pool slot `n` listens on `BASE_PORT + n`.
+const BASE_PORT: u16 = 4444;
+
+/// How often the page-ready conditions are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the network or the DOM must stay unchanged to count as settled.
+const QUIET_PERIOD: Duration = Duration::from_millis(500);
+
+/// Whether an element matches the selector passed as the first argument.
+const SELECTOR_SCRIPT: &str = "return document.querySelector(arguments[0]) !== null;";
+
+/// The HTTP status the document was served with, or 0 when the browser does
+/// not report it.
+const STATUS_SCRIPT: &str = "const entry = performance.getEntriesByType('navigation')[0]; \
+    return entry && entry.responseStatus ? entry.responseStatus : 0;";
+
+/// The `ETag` and `Last-Modified` the document was served with, read back
+/// from the browser's HTTP cache so that no request reaches the host; null
+/// when the response was not cached.
+const VALIDATORS_SCRIPT: &str = "const done = arguments[0]; \
+    fetch(location.href, { cache: 'only-if-cached', mode: 'same-origin' }) \
+        .then((response) => done(response.ok \
+            ? [response.headers.get('ETag'), response.headers.get('Last-Modified')] \
+            : null), () => done(null));";
+
+/// Whether the document and its subresources have loaded.
+const LOAD_SCRIPT: &str = "return document.readyState === 'complete';";
+
+/// How many requests have finished so far, or -1 while the document loads.
+/// Finished requests are counted by an observer installed on the first call,
+/// since the resource timing buffer stops growing once it is full.
+const NETWORK_SCRIPT: &str = "if (document.readyState !== 'complete') return -1; \
+    if (window.__crawlerFinishedRequests === undefined) { \
+    window.__crawlerFinishedRequests = 0; \
+    new PerformanceObserver((list) => { window.__crawlerFinishedRequests += list.getEntries().length; }) \
+        .observe({ type: 'resource', buffered: true }); \
+    } \
+    return window.__crawlerFinishedRequests;";
+
+/// Milliseconds since the DOM last changed, watched by an observer installed
+/// on the first call.
+const DOM_QUIET_SCRIPT: &str = "if (window.__crawlerLastMutation === undefined) { \
+    window.__crawlerLastMutation = Date.now(); \
+    new MutationObserver(() => { window.__crawlerLastMutation = Date.now(); }) \
+        .observe(document, { subtree: true, childList: true, attributes: true, characterData: true }); \
+    } \
+    return Date.now() - window.__crawlerLastMutation;";
+
+/// How long auto-scroll pauses after each step for content to render.
+const SCROLL_PAUSE: Duration = Duration::from_millis(250);
+
+/// Scrolls down by the number of pixels passed as the first argument and
+/// returns the page height once the bottom is reached, `null` before that.
+const SCROLL_SCRIPT: &str = "window.scrollBy(0, arguments[0]); \
+    const height = document.documentElement.scrollHeight; \
+    return window.scrollY + window.innerHeight >= height - 1 ? height : null;";
+
+/// Longest auto-scroll waits at the bottom of the page for content loaded
+/// on scroll to settle.
+const SCROLL_SETTLE: Duration = Duration::from_secs(2);
+
+/// Starts recording the elements removed from the page while it scrolls, so
+/// that virtualized lists, which drop the items scrolled past, can be put
+/// back together.
+const SCROLL_WATCH_SCRIPT: &str = "window.__crawlerRemoved = []; \
+    new MutationObserver((mutations) => { \
+        for (const mutation of mutations) { \
+            for (const node of mutation.removedNodes) { \
+                if (node.nodeType === Node.ELEMENT_NODE) window.__crawlerRemoved.push([mutation.target, node]); \
+            } \
+        } \
+    }).observe(document.body, { childList: true, subtree: true });";
+
+/// Puts back the removed elements that look like the list items still in
+/// their parent (same tag and classes), ahead of them in the order they were
+/// scrolled past, leaving out any item that is on the page again.
+const SCROLL_RESTORE_SCRIPT: &str = r#"
+const removed = new Map();
+for (const [parent, node] of window.__crawlerRemoved || []) {
+    if (!parent.isConnected || node.isConnected) continue;
+    if (!removed.has(parent)) removed.set(parent, []);
+    removed.get(parent).push(node);
+}
+const shape = (element) => element.tagName + '.' + element.className;
+for (const [parent, nodes] of removed) {
+    const items = [...parent.children];
+    const shapes = new Set(items.map(shape));
+    const seen = new Set(items.map((item) => item.outerHTML));
+    const restored = nodes.filter((node) => {
+        if (!shapes.has(shape(node)) || seen.has(node.outerHTML)) return false;
+        seen.add(node.outerHTML);
+        return true;
+    });
+    parent.prepend(...restored);
+}
+window.__crawlerRemoved = [];
+"#;
+
+/// Clicks every element matching the selector passed as the first argument.
+const CLICK_SCRIPT: &str =
+    "document.querySelectorAll(arguments[0]).forEach((element) => element.click());";
+
+/// The built-in expansion pass, run asynchronously inside the first content
+/// element matching one of the selectors passed as the first argument. It
+/// opens every `<details>`, clicks collapsed accordion toggles and "show
+/// more" buttons outside the page chrome, and makes every tab panel part of
+/// the page, labelled with its tab in `data-tab-label`. Tab widgets that only
+/// render the selected panel are shown one tab at a time and a copy of each
+/// panel is kept. Nothing that would leave the page, such as a link or a
+/// form's submit button, is clicked.
+const EXPAND_SCRIPT: &str = r#"
+const [scope, done] = arguments;
+const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+const root = scope.map((selector) => document.querySelector(selector)).find(Boolean) || document.body;
+const chrome = 'nav, header, footer, aside, [role="navigation"]';
+const showMore = /^\s*(show|see|view|read|load|expand)\s+(more|all)/i;
+const label = (tab) => tab.textContent.trim();
+const navigates = (element) => {
+    const link = element.closest('a[href]');
+    const href = link ? link.getAttribute('href').trim() : '';
+    return (href !== '' && !href.startsWith('#') && !href.toLowerCase().startsWith('javascript:'))
+        || (element.type === 'submit' && element.form != null);
+};
+(async () => {
+    root.querySelectorAll('details:not([open])').forEach((details) => { details.open = true; });
+    for (const button of root.querySelectorAll('button, [role="button"]')) {
+        if (button.closest(chrome) || navigates(button)) continue;
+        if (button.getAttribute('aria-expanded') === 'false' || showMore.test(button.textContent)) {
+            button.click();
+        }
+    }
+    await sleep(200);
+    for (const list of root.querySelectorAll('[role="tablist"]')) {
+        if (list.closest(chrome)) continue;
+        const tabs = [...list.querySelectorAll('[role="tab"]')];
+        const panels = tabs.map((tab) => document.getElementById(tab.getAttribute('aria-controls')));
+        if (panels.every(Boolean)) {
+            panels.forEach((panel, i) => {
+                panel.removeAttribute('hidden');
+                panel.removeAttribute('aria-hidden');
+                panel.setAttribute('data-tab-label', label(tabs[i]));
+            });
+            continue;
+        }
+        if (tabs.some(navigates)) continue;
+        const container = list.parentElement;
+        const copies = [];
+        for (const tab of tabs) {
+            tab.click();
+            await sleep(200);
+            const panel = [...container.querySelectorAll('[role="tabpanel"]')].find((panel) => !panel.hidden);
+            if (!panel) break;
+            const copy = panel.cloneNode(true);
+            copy.removeAttribute('id');
+            copy.setAttribute('data-tab-label', label(tab));
+            copies.push(copy);
+        }
+        if (copies.length === tabs.length) {
+            container.querySelectorAll('[role="tabpanel"]').forEach((panel) => panel.remove());
+            container.append(...copies);
+        }
+    }
+})().then(() => done(true), () => done(false));
+"#;
+
+/// `url` without its fragment, which in-page toggles may change.
+fn without_fragment(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url
+}
+
+/// Kills GeckoDriver processes left behind by an earlier, interrupted crawl.
+pub async fn kill_stray_drivers() {
+    let _ = Command::new("pkill").args(["-f", "geckodriver"]).output();
+
+    // Wait a moment for the processes to be cleaned up
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+impl GeckoDriver {
+    async fn new(port: u16) -> Result<Self, Crawler> {
+        println!("Starting GeckoDriver on port {}...", port);
+        let process = Command::new("geckodriver")
+            .arg("--port")
+            .arg(port.to_string())
+            .spawn()
+            .map_err(|e| Crawler::Browser(e.to_string()))?;
+
+        // Wait for the driver to start
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        Ok(GeckoDriver { process })
+    }
+
+    fn cleanup(&mut self) {
+        println!("Stopping GeckoDriver...");
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+impl Drop for GeckoDriver {
+    fn drop(&mut self) {
+        self.cleanup();
+    }
+}
+
+/// A headless Firefox session reused for every page and image fetch it is handed.
+///
+/// Each session owns its own GeckoDriver, started once in
+/// [`BrowserSession::start`] and stopped when the session is closed or dropped.
+pub struct BrowserSession {
+    client: Client,
+    http: HttpFetcher,
+    /// When a navigated page counts as rendered
+    ready: PageReady,
+    /// What is opened up in a rendered page before it is read
+    expansion: Expansion,
+    _driver: GeckoDriver,
+}
+
+impl BrowserSession {
+    /// Starts the GeckoDriver for pool slot `slot` and opens a session on it.
+    pub async fn start(
+        slot: usize,
+        user_agent: &str,
+        ready: PageReady,
+        expansion: Expansion,
+    ) -> Result<Self, Crawler> {
+        let port = u16::try_from(slot)
+            .ok()
+            .and_then(|slot| BASE_PORT.checked_add(slot))
+            .ok_or_else(|| {
+                Crawler::Browser(format!("No WebDriver port left for session {}", slot))
+            })?;
+        let mut driver = None;
+        let mut last_error = None;
+        let max_retries = 3;
+
+        for _ in 0..max_retries {
+            match GeckoDriver::new(port).await {
+                Ok(d) => {
+                    driver = Some(d);
+                    break;
+                }
+                Err(e) => {
+                    last_error = Some(e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+
+        let driver = driver.ok_or_else(|| {
+            Crawler::Browser(format!(
+                "Failed to start GeckoDriver after retries: {:?}",
+                last_error
+            ))
+        })?;
+
+        // Create capabilities using serde_json's Map
+        let mut caps = serde_json::Map::new();
+        let mut firefox_opts = serde_json::Map::new();
+        firefox_opts.insert(
+            "args".to_string(),
+            serde_json::Value::Array(vec![serde_json::Value::String("--headless".to_string())]),
+        );
+        let mut prefs = serde_json::Map::new();
+        prefs.insert(
+            "general.useragent.override".to_string(),
+            serde_json::Value::String(user_agent.to_string()),
+        );
+        firefox_opts.insert("prefs".to_string(), serde_json::Value::Object(prefs));
+        caps.insert(
+            "moz:firefoxOptions".to_string(),
+            serde_json::Value::Object(firefox_opts),
+        );
+
+        let client = ClientBuilder::native()
+            .capabilities(caps)
+            .connect(&format!("http://localhost:{}", port))
+            .await
+            .map_err(|e| Crawler::Browser(format!("Failed to connect to WebDriver: {}", e)))?;
+
+        Ok(BrowserSession {
+            client,
+            http: HttpFetcher::new(user_agent),
+            ready,
+            expansion,
+            _driver: driver,
+        })
+    }
+
+    /// Waits for each of the page-ready conditions in turn. Once the timeout
+    /// has run out the page is read as it is.
+    async fn wait_until_ready(&self, url: &str) {
+        let deadline = Instant::now() + self.ready.timeout;
+        for strategy in &self.ready.strategies {
+            if !self.wait_for(strategy, deadline).await {
+                println!(
+                    "Timed out waiting for {} on {}; reading the page as it is",
+                    strategy, url
+                );
+                return;
+            }
+        }
+    }
+
+    /// Reveals collapsed content: runs the built-in expansion pass, then the
+    /// site's steps, letting the DOM settle after each.
+    async fn expand(&self, url: &str) {
+        if self.expansion.scroll.enabled {
+            self.scroll_to_bottom(url, self.expansion.scroll).await;
+        }
+        let deadline = Instant::now() + self.ready.timeout;
+        if self.expansion.builtin {
+            let scope = Value::from(self.expansion.scope.clone());
+            if let Err(e) = self.client.execute_async(EXPAND_SCRIPT, vec![scope]).await {
+                println!("Failed to expand collapsed content on {}: {}", url, e);
+            }
+            self.wait_for(&WaitStrategy::DomStable, deadline).await;
+        }
+        for step in &self.expansion.steps {
+            let result = match step {
+                Interaction::Click(selector) => {
+                    self.client
+                        .execute(CLICK_SCRIPT, vec![Value::from(selector.as_str())])
+                        .await
+                }
+                Interaction::Script(script) => self.client.execute(script, Vec::new()).await,
+            };
+            if let Err(e) = result {
+                println!("Expansion step {} failed on {}: {}", step, url, e);
+            }
+            self.wait_for(&WaitStrategy::DomStable, deadline).await;
+        }
+    }
+
+    /// Scrolls down in steps until the bottom of the page stays put, waiting
+    /// briefly at the bottom for content loaded on scroll, or until the step
+    /// limit. List items removed along the way are put back afterwards.
+    async fn scroll_to_bottom(&self, url: &str, scroll: AutoScroll) {
+        self.script(SCROLL_WATCH_SCRIPT, Vec::new()).await;
+        let mut bottom = None;
+        let mut settled = false;
+        for _ in 0..scroll.limit {
+            let height = self
+                .script(SCROLL_SCRIPT, vec![Value::from(scroll.step)])
+                .await
+                .as_f64();
+            if height.is_some() && height == bottom {
+                settled = true;
+                break;
+            }
+            bottom = height;
+            if bottom.is_some() {
+                let deadline = Instant::now() + SCROLL_SETTLE;
+                self.wait_for(&WaitStrategy::DomStable, deadline).await;
+            } else {
+                tokio::time::sleep(SCROLL_PAUSE).await;
+            }
+        }
+        if !settled {
+            println!(
+                "Stopped scrolling {} after {} steps; reading the page as it is",
+                url, scroll.limit
+            );
+        }
+        self.script(SCROLL_RESTORE_SCRIPT, Vec::new()).await;
+    }
+
+    /// Polls `strategy` until it holds or `deadline` passes, returning
+    /// whether it held.
+    async fn wait_for(&self, strategy: &WaitStrategy, deadline: Instant) -> bool {
+        let mut finished_requests = None;
+        let mut quiet_since = Instant::now();
+        loop {
+            let ready = match strategy {
+                WaitStrategy::Load => {
+                    self.script(LOAD_SCRIPT, Vec::new()).await == Value::Bool(true)
+                }
+                WaitStrategy::Selector(selector) => {
+                    self.script(SELECTOR_SCRIPT, vec![Value::from(selector.as_str())])
+                        .await
+                        == Value::Bool(true)
+                }
+                WaitStrategy::NetworkIdle => {
+                    let count = self.script(NETWORK_SCRIPT, Vec::new()).await.as_i64();
+                    if count != finished_requests || count.is_none_or(|count| count < 0) {
+                        finished_requests = count;
+                        quiet_since = Instant::now();
+                    }
+                    quiet_since.elapsed() >= QUIET_PERIOD
+                }
+                WaitStrategy::DomStable => self
+                    .script(DOM_QUIET_SCRIPT, Vec::new())
+                    .await
+                    .as_f64()
+                    .is_some_and(|quiet| quiet >= QUIET_PERIOD.as_millis() as f64),
+            };
+            if ready {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// The validators the rendered document was served with, as far as the
+    /// browser's cache knows them.
+    async fn cached_validators(&self) -> Validators {
+        let result = self
+            .client
+            .execute_async(VALIDATORS_SCRIPT, Vec::new())
+            .await
+            .unwrap_or(Value::Null);
+        let header = |i: usize| result.get(i).and_then(Value::as_str).map(str::to_string);
+        Validators {
+            etag: header(0),
+            last_modified: header(1),
+        }
+    }
+
+    /// Runs `script` in the page; errors, such as a navigation replacing the
+    /// document mid-call, read as `null`.
+    async fn script(&self, script: &str, args: Vec<Value>) -> Value {
+        self.client
+            .execute(script, args)
+            .await
+            .unwrap_or(Value::Null)
+    }
+}
+
+#[async_trait]
+impl Fetcher for BrowserSession {
+    /// Navigates to `url` and returns the rendered page source.
+    async fn fetch_html(&self, url: &str) -> Result<String, Crawler> {
+        self.client
+            .goto(url)
+            .await
+            .map_err(|e| Crawler::Network(format!("Failed to navigate to URL: {}", e)))?;
+
+        // WebDriver renders error pages like any other, so the status is read
+        // from the page to let throttling and server errors be retried
+        let status = self.script(STATUS_SCRIPT, Vec::new()).await.as_u64();
+        if let Some(status) = status.filter(|status| *status >= 400) {
+            return Err(Crawler::Http {
+                url: url.to_string(),
+                status: status as u16,
+                retry_after: None,
+            });
+        }
+
+        self.wait_until_ready(url).await;
+        let landed = self.client.current_url().await.ok();
+        self.expand(url).await;
+
+        // A click that navigated elsewhere would save another page under this
+        // URL, so the page is loaded again and read without expanding it
+        if let Some(landed) = landed {
+            let current = self.client.current_url().await.ok();
+            if current.as_ref().map(without_fragment) != Some(without_fragment(&landed)) {
+                println!(
+                    "Expanding {} navigated away from it; reading the page unexpanded",
+                    url
+                );
+                self.client
+                    .goto(landed.as_str())
+                    .await
+                    .map_err(|e| Crawler::Network(format!("Failed to navigate to URL: {}", e)))?;
+                self.wait_until_ready(url).await;
+            }
+        }
+
+        self.client
+            .source()
+            .await
+            .map_err(|e| Crawler::Browser(format!("Failed to get page source: {}", e)))
+    }
+
+    /// Downloads `url` over HTTP, since navigating to it would only return the
+    /// HTML page Firefox wraps around the file.
+    async fn fetch_bytes(&self, url: &str) -> Result<Vec<u8>, Crawler> {
+        Ok(self.fetch_asset(url, usize::MAX).await?.bytes)
+    }
+
+    /// Downloads `url` over HTTP with the browser's cookies, so assets behind
+    /// a session the page set up still load.
+    async fn fetch_asset(&self, url: &str, max_size: usize) -> Result<Asset, Crawler> {
+        let cookies = self.client.get_all_cookies().await.unwrap_or_default();
+        let cookie = Url::parse(url)
+            .ok()
+            .and_then(|parsed| cookie_header(&cookies, &parsed));
+        self.http.download(url, max_size, cookie).await
+    }
+
+    /// Asks the server with a conditional `HEAD` before rendering, since
+    /// WebDriver itself cannot send conditional requests. A page without
+    /// validators is rendered straight away, and the validators it was served
+    /// with are read from the browser's cache.
+    async fn fetch_html_if_modified(
+        &self,
+        url: &str,
+        known: &Validators,
+    ) -> Result<Conditional, Crawler> {
+        // Without validators there is nothing to ask, so the probe would only
+        // cost the host a second request
+        let probed = if known.is_empty() {
+            None
+        } else {
+            match self.http.probe(url, known).await {
+                Ok(None) => return Ok(Conditional::NotModified),
+                Ok(Some(validators)) => Some(validators),
+                // Throttling and server errors are left to the caller's backoff
+                Err(e) if is_retryable(&e) => return Err(e),
+                // Servers that reject HEAD are simply rendered every time
+                Err(_) => Some(Validators::default()),
+            }
+        };
+
+        let html = self.fetch_html(url).await?;
+        let validators = match probed {
+            Some(validators) => validators,
+            None => self.cached_validators().await,
+        };
+        Ok(Conditional::Modified { html, validators })
+    }
+
+    /// Ends the WebDriver session, then stops GeckoDriver.
+    async fn close(self: Box<Self>) {
+        let session = *self;
+        if let Err(e) = session.client.close().await {
+            eprintln!("Failed to close WebDriver session: {}", e);
+        }
+    }
+}
+
+/// Builds a `Cookie` header from the browser cookies that would be sent to `url`.
+fn cookie_header(cookies: &[Cookie<'static>], url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    let pairs: Vec<String> = cookies
+        .iter()
+        .filter(|cookie| {
+            let domain_matches = cookie.domain().is_none_or(|domain| {
+                let domain = domain.trim_start_matches('.');
+                host == domain || host.ends_with(&format!(".{}", domain))
+            });
+            let path_matches = cookie
+                .path()
+                .is_none_or(|path| url.path().starts_with(path));
+            let scheme_matches = !cookie.secure().unwrap_or(false) || url.scheme() == "https";
+            domain_matches && path_matches && scheme_matches
+        })
+        .map(|cookie| format!("{}={}", cookie.name(), cookie.value()))
+        .collect();
+    (!pairs.is_empty()).then(|| pairs.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cookie_header_matches_domain_path_and_scheme() {
+        let cookies = vec![
+            Cookie::build(("session", "abc"))
+                .domain(".example.com")
+                .path("/")
+                .build(),
+            Cookie::build(("docs", "1"))
+                .domain("docs.example.com")
+                .path("/assets")
+                .build(),
+            Cookie::build(("secure", "2")).secure(true).build(),
+            Cookie::build(("other", "3")).domain("other.com").build(),
+        ];
+        let url = Url::parse("https://docs.example.com/assets/logo.png").unwrap();
+        assert_eq!(
+            cookie_header(&cookies, &url).as_deref(),
+            Some("session=abc; docs=1; secure=2")
+        );
+        let url = Url::parse("http://example.com/img.png").unwrap();
+        assert_eq!(
+            cookie_header(&cookies, &url).as_deref(),
+            Some("session=abc")
+        );
+        let url = Url::parse("http://unrelated.org/").unwrap();
+        assert_eq!(cookie_header(&cookies, &url), None);
+    }
+}
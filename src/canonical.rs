@@ -0,0 +1,134 @@
+use scraper::{Html, Selector};
+use url::Url;
+
+/// Query parameters that only track where a visitor came from and never
+/// change the page served.
+const TRACKING_PARAMS: &[&str] = &[
+    "gclid", "dclid", "fbclid", "msclkid", "mc_cid", "mc_eid", "_ga", "_gl", "yclid", "igshid",
+    "ref_src",
+];
+
+/// Directory index files that serve the same page as the directory itself.
+const INDEX_FILES: &[&str] = &["index.html", "index.htm"];
+
+fn is_tracking_param(name: &str) -> bool {
+    name.starts_with("utm_") || TRACKING_PARAMS.contains(&name)
+}
+
+/// Cleans `url` into the form it is fetched and recorded under: the fragment
+/// and tracking parameters are dropped, and the scheme and host lowercased.
+///
+/// The path is left alone, since servers differ on whether `/guide` and
+/// `/guide/` resolve relative links the same way. URLs that do not parse are
+/// returned unchanged.
+pub fn normalize(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+    parsed.set_fragment(None);
+
+    // Only rewrite queries that need it, since re-serializing changes their encoding
+    if parsed
+        .query_pairs()
+        .any(|(name, _)| is_tracking_param(&name))
+    {
+        let kept: Vec<(String, String)> = parsed
+            .query_pairs()
+            .filter(|(name, _)| !is_tracking_param(name))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+        if kept.is_empty() {
+            parsed.set_query(None);
+        } else {
+            parsed.query_pairs_mut().clear().extend_pairs(kept);
+        }
+    }
+    parsed.to_string()
+}
+
+/// The identity of a page: two URLs with the same key are the same page.
+///
+/// On top of [`normalize`], a trailing `index.html` and a trailing slash are
+/// removed, so `/guide/`, `/guide` and `/guide/index.html` share one key.
+pub fn key(url: &str) -> String {
+    let normalized = normalize(url);
+    let Ok(mut parsed) = Url::parse(&normalized) else {
+        return normalized;
+    };
+
+    let mut path = parsed.path().to_string();
+    for index in INDEX_FILES {
+        if let Some(dir) = path.strip_suffix(index) {
+            if dir.ends_with('/') {
+                path = dir.to_string();
+                break;
+            }
+        }
+    }
+    if path.len() > 1 {
+        path = path.trim_end_matches('/').to_string();
+    }
+    parsed.set_path(&path);
+    parsed.to_string()
+}
+
+/// Returns the absolute URL of the page's `<link rel="canonical">`, if any.
+pub fn declared_canonical(html: &str, page_url: &str) -> Option<String> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("link[rel~='canonical'][href]").unwrap();
+    let href = document.select(&selector).next()?.value().attr("href")?;
+    let absolute = Url::parse(page_url).ok()?.join(href.trim()).ok()?;
+    matches!(absolute.scheme(), "http" | "https").then(|| normalize(absolute.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_strips_tracking_and_fragments() {
+        assert_eq!(
+            normalize("HTTPS://Docs.Example.com/guide/?utm_source=x&page=2&fbclid=abc#setup"),
+            "https://docs.example.com/guide/?page=2"
+        );
+        assert_eq!(
+            normalize("https://example.com/a?utm_medium=email"),
+            "https://example.com/a"
+        );
+        assert_eq!(normalize("not a url"), "not a url");
+    }
+
+    #[test]
+    fn test_key_collapses_index_and_trailing_slash() {
+        let expected = "https://example.com/guide";
+        assert_eq!(key("https://example.com/guide"), expected);
+        assert_eq!(key("https://example.com/guide/"), expected);
+        assert_eq!(key("https://example.com/guide/index.html"), expected);
+        assert_eq!(key("https://example.com/guide/#intro"), expected);
+        assert_eq!(key("https://example.com/"), "https://example.com/");
+        assert_eq!(
+            key("https://example.com/index.html"),
+            "https://example.com/"
+        );
+        assert_eq!(
+            key("https://example.com/myindex.html"),
+            "https://example.com/myindex.html"
+        );
+    }
+
+    #[test]
+    fn test_declared_canonical() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="/style.css">
+            <link rel="canonical" href="/docs/intro/?utm_source=feed">
+        </head><body></body></html>"#;
+        assert_eq!(
+            declared_canonical(html, "https://example.com/v2/docs/intro"),
+            Some("https://example.com/docs/intro/".to_string())
+        );
+        assert_eq!(
+            declared_canonical("<p>No head</p>", "https://example.com/"),
+            None
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use crate::expand::Interaction;
+use crate::markdown::AdmonitionStyle;
+use crate::profile::{Profile, ProfileName, Replacement};
+use crate::url_reader::Crawler;
+use crate::wait::WaitStrategy;
+use scraper::Selector;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Extraction rules for one site, read from the TOML file given with
+/// `--config`, so sites no built-in profile covers can be described without
+/// recompiling.
+///
+/// Every key is optional. The rules start from a built-in profile; lists
+/// given in the file replace the profile's, except `remove` and `replace`,
+/// which add to it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SiteConfig {
+    /// Built-in profile the rules start from, used unless `--profile` names one
+    pub profile: Option<ProfileName>,
+    pub content: Option<Vec<String>>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+    pub title: Option<Vec<String>>,
+    pub nav: Option<Vec<String>>,
+    pub follow: Option<Vec<String>>,
+    pub wait: Option<Vec<WaitStrategy>>,
+    pub expand: Option<Vec<Interaction>>,
+    pub scroll: Option<ScrollRule>,
+    #[serde(default)]
+    pub replace: Vec<ReplaceRule>,
+    pub admonitions: Option<AdmonitionStyle>,
+}
+
+/// A `[[replace]]` table: every match of `pattern` in the extracted markdown
+/// becomes `with`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplaceRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub with: String,
+}
+
+/// The `[scroll]` table, which turns auto-scroll on unless `enabled` is
+/// `false`; `step` and `limit` default to the profile's.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScrollRule {
+    pub enabled: Option<bool>,
+    pub step: Option<u32>,
+    pub limit: Option<u32>,
+}
+
+impl SiteConfig {
+    /// Reads and validates the rules in `path`.
+    pub fn load(path: &Path) -> Result<Self, Crawler> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text)
+            .map_err(|e| Crawler::Parsing(format!("Invalid config {}: {}", path.display(), e)))
+    }
+
+    /// Parses rules from TOML, checking every selector and pattern up front
+    /// so that a typo fails before the crawl starts.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: SiteConfig = toml::from_str(text).map_err(|e| e.to_string())?;
+
+        let selectors = config
+            .content
+            .iter()
+            .flatten()
+            .chain(&config.remove)
+            .chain(config.title.iter().flatten())
+            .chain(config.nav.iter().flatten())
+            .chain(config.follow.iter().flatten());
+        for selector in selectors {
+            Selector::parse(selector)
+                .map_err(|e| format!("invalid selector {:?}: {:?}", selector, e))?;
+        }
+        for rule in &config.replace {
+            Replacement::new(&rule.pattern, &rule.with)
+                .map_err(|e| format!("invalid pattern {:?}: {}", rule.pattern, e))?;
+        }
+        if let Some(scroll) = &config.scroll {
+            if scroll.step == Some(0) || scroll.limit == Some(0) {
+                return Err("scroll step and limit must be greater than 0".to_string());
+            }
+        }
+        Ok(config)
+    }
+
+    /// Layers these rules over `profile`.
+    pub fn apply(&self, mut profile: Profile) -> Profile {
+        if let Some(content) = &self.content {
+            profile.content = content.clone();
+        }
+        profile.remove.extend(self.remove.iter().cloned());
+        if let Some(title) = &self.title {
+            profile.title = title.clone();
+        }
+        if let Some(nav) = &self.nav {
+            profile.nav = nav.clone();
+        }
+        if let Some(follow) = &self.follow {
+            profile.follow = follow.clone();
+        }
+        if let Some(wait) = &self.wait {
+            profile.wait = wait.clone();
+        }
+        if let Some(expand) = &self.expand {
+            profile.expand = expand.clone();
+        }
+        if let Some(scroll) = &self.scroll {
+            profile.scroll.enabled = scroll.enabled.unwrap_or(true);
+            profile.scroll.step = scroll.step.unwrap_or(profile.scroll.step);
+            profile.scroll.limit = scroll.limit.unwrap_or(profile.scroll.limit);
+        }
+        if let Some(admonitions) = self.admonitions {
+            profile.admonitions = admonitions;
+        }
+        profile.replace.extend(
+            self.replace
+                .iter()
+                .filter_map(|rule| Replacement::new(&rule.pattern, &rule.with).ok()),
+        );
+        profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expand::AutoScroll;
+
+    const EXAMPLE: &str = r#"
+        profile = "docusaurus"
+        content = ["div.portal-body"]
+        remove = [".rating-widget"]
+        title = ["h1.page-title"]
+        follow = ["nav.portal-tree"]
+        wait = ["selector:div.portal-body", "dom-stable"]
+        expand = ["click:button.load-examples"]
+        admonitions = "mkdocs"
+
+        [scroll]
+        limit = 200
+
+        [[replace]]
+        pattern = '^Was this page helpful\?.*\n'
+
+        [[replace]]
+        pattern = 'Internal Portal'
+        with = "Portal"
+    "#;
+
+    #[test]
+    fn test_apply_over_profile() {
+        let config = SiteConfig::parse(EXAMPLE).unwrap();
+        assert_eq!(config.profile, Some(ProfileName::Docusaurus));
+
+        let base = ProfileName::Docusaurus.profile().unwrap();
+        let profile = config.apply(base.clone());
+        assert_eq!(profile.content, vec!["div.portal-body"]);
+        assert_eq!(profile.title, vec!["h1.page-title"]);
+        assert_eq!(profile.follow, vec!["nav.portal-tree"]);
+        assert_eq!(profile.nav, base.nav);
+        assert_eq!(
+            profile.wait,
+            vec![
+                WaitStrategy::Selector("div.portal-body".to_string()),
+                WaitStrategy::DomStable
+            ]
+        );
+        assert_eq!(
+            profile.expand,
+            vec![Interaction::Click("button.load-examples".to_string())]
+        );
+        assert_eq!(
+            profile.scroll,
+            AutoScroll {
+                enabled: true,
+                step: base.scroll.step,
+                limit: 200
+            }
+        );
+        assert_eq!(profile.admonitions, AdmonitionStyle::Mkdocs);
+        assert_eq!(profile.remove.len(), base.remove.len() + 1);
+        assert_eq!(profile.remove.last().unwrap(), ".rating-widget");
+        assert_eq!(
+            profile.rewrite("Internal Portal guide\nWas this page helpful? Yes No\n"),
+            "Portal guide\n"
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_rules() {
+        let error = SiteConfig::parse(r#"content = ["div > > p"]"#).unwrap_err();
+        assert!(error.contains("invalid selector"), "{}", error);
+
+        let error = SiteConfig::parse("[[replace]]\npattern = '(unclosed'").unwrap_err();
+        assert!(error.contains("invalid pattern"), "{}", error);
+
+        assert!(SiteConfig::parse(r#"wait = ["idle"]"#).is_err());
+        assert!(SiteConfig::parse(r#"expand = ["hover:.menu"]"#).is_err());
+        assert!(SiteConfig::parse("contents = []").is_err());
+        assert!(SiteConfig::parse("[scroll]\nstep = 0").is_err());
+        assert!(SiteConfig::parse(r#"profile = "wordpress""#).is_err());
+    }
+}
@@ -0,0 +1,674 @@
+use crate::canonical;
+use crate::config::SiteConfig;
+use crate::expand::{AutoScroll, Expansion, Interaction};
+use crate::fetcher::{self, Backend, Fetcher, HttpFetcher, Validators};
+use crate::filter::UrlFilter;
+use crate::layout::{self, PathAllocator, PATH_MAP_FILE, SUMMARY_FILE};
+use crate::links::LinkMap;
+use crate::markdown::AdmonitionStyle;
+use crate::politeness::{PoliteFetcher, RateLimiter};
+use crate::profile::{self, Profile, ProfileName};
+use crate::robots::RobotsCache;
+use crate::sitemap;
+use crate::state::{self, content_hash, CrawlState, CrawlSummary, Manifest, PageRecord};
+use crate::url_reader::{fetch_page, Crawler};
+use crate::wait::{PageReady, WaitStrategy};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+pub struct CrawlConfig {
+    pub start_url: String,
+    pub output_dir: PathBuf,
+    pub max_pages: usize,
+    pub max_depth: usize,
+    pub backend: Backend,
+    pub concurrency: usize,
+    pub requests_per_second: f64,
+    pub max_retries: u32,
+    pub user_agent: String,
+    pub respect_robots: bool,
+    pub use_sitemaps: bool,
+    /// Only crawl sitemap pages whose `<lastmod>` is on or after this `YYYY-MM-DD` date
+    pub since: Option<String>,
+    pub resume: bool,
+    pub filter: UrlFilter,
+    /// Site profile to extract pages with; [`ProfileName::Auto`] detects it
+    pub profile: ProfileName,
+    /// Extraction rules from `--config`, layered over the profile
+    pub site_config: Option<SiteConfig>,
+    /// Page-ready conditions overriding the profile's; empty keeps them
+    pub wait: Vec<WaitStrategy>,
+    /// How long the browser waits for a page to be ready
+    pub wait_timeout: Duration,
+    /// Whether the browser opens collapsed sections and tab panels before
+    /// reading a page
+    pub expand_hidden: bool,
+    /// Expansion steps run after the profile's
+    pub expand: Vec<Interaction>,
+    /// Turns on auto-scroll whatever the profile says
+    pub scroll: bool,
+    /// How admonitions are written, overriding the config file
+    pub admonitions: Option<AdmonitionStyle>,
+}
+
+/// Breadth-first queue of pending `(url, depth)` entries.
+///
+/// Every URL is remembered by its [`canonical::key`] when it is first queued,
+/// so a page linked from many places, or under several spellings of its URL,
+/// is still fetched only once.
+#[derive(Serialize, Deserialize)]
+pub struct Frontier {
+    queue: VecDeque<(String, usize)>,
+    visited: HashSet<String>,
+}
+
+impl Frontier {
+    pub fn new(start_url: &str) -> Self {
+        let mut frontier = Frontier {
+            queue: VecDeque::new(),
+            visited: HashSet::new(),
+        };
+        frontier.push(start_url.to_string(), 0);
+        frontier
+    }
+
+    /// Queues `url` unless it has been seen before. Returns whether it was added.
+    pub fn push(&mut self, url: String, depth: usize) -> bool {
+        if !self.visited.insert(canonical::key(&url)) {
+            return false;
+        }
+        self.queue.push_back((canonical::normalize(&url), depth));
+        true
+    }
+
+    /// Records `url` as handled without queueing it, returning `false` when it
+    /// was already seen.
+    pub fn mark_visited(&mut self, url: &str) -> bool {
+        self.visited.insert(canonical::key(url))
+    }
+
+    pub fn pop(&mut self) -> Option<(String, usize)> {
+        self.queue.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+pub async fn crawl(config: &CrawlConfig) -> Result<(), Crawler> {
+    Url::parse(&config.start_url)
+        .map_err(|e| Crawler::Parsing(format!("Invalid start URL {}: {}", config.start_url, e)))?;
+
+    let limiter = Arc::new(RateLimiter::new(config.requests_per_second));
+    let robots = config
+        .respect_robots
+        .then(|| RobotsCache::new(&config.user_agent, limiter.clone(), config.max_retries));
+    let mut profile = resolve_profile(config, &limiter).await;
+    if let Some(admonitions) = config.admonitions {
+        profile.admonitions = admonitions;
+    }
+    println!("Using the {} site profile", profile.name);
+    let ready = PageReady {
+        strategies: if config.wait.is_empty() {
+            profile.wait.clone()
+        } else {
+            config.wait.clone()
+        },
+        timeout: config.wait_timeout,
+    };
+    let expansion = Expansion {
+        scroll: AutoScroll {
+            enabled: profile.scroll.enabled || config.scroll,
+            ..profile.scroll
+        },
+        builtin: config.expand_hidden,
+        scope: profile.content.clone(),
+        steps: profile
+            .expand
+            .iter()
+            .chain(&config.expand)
+            .cloned()
+            .collect(),
+    };
+    let pool: Vec<Box<dyn Fetcher>> = fetcher::connect_pool(
+        config.backend,
+        &config.start_url,
+        config.concurrency.max(1),
+        &config.user_agent,
+        &ready,
+        &expansion,
+    )
+    .await?
+    .into_iter()
+    .map(|fetcher| {
+        Box::new(PoliteFetcher::new(
+            fetcher,
+            limiter.clone(),
+            config.max_retries,
+        )) as Box<dyn Fetcher>
+    })
+    .collect();
+    let result = crawl_with(config, &profile, &pool, &limiter, robots.as_ref()).await;
+    fetcher::close_pool(pool).await;
+    result
+}
+
+/// Runs the crawl, fetching up to one page per pooled fetcher at a time.
+///
+/// Each batch is taken from the front of the frontier and its results are
+/// handled in that same order, so files, links and the index come out the same
+/// no matter which fetch finishes first.
+async fn crawl_with(
+    config: &CrawlConfig,
+    profile: &Profile,
+    pool: &[Box<dyn Fetcher>],
+    limiter: &Arc<RateLimiter>,
+    robots: Option<&RobotsCache>,
+) -> Result<(), Crawler> {
+    let previous = Manifest::load(&config.output_dir)?.by_url();
+    let mut state = match resume_state(config)? {
+        Some(state) => state,
+        None => {
+            let mut frontier = Frontier::new(&config.start_url);
+            let mut carried = Vec::new();
+            if config.use_sitemaps && config.max_depth > 0 {
+                carried =
+                    seed_from_sitemaps(config, &mut frontier, &previous, limiter, robots).await;
+            }
+            // Pages left alone by --since still lead to the pages they link to
+            for record in &carried {
+                if record.depth < config.max_depth {
+                    for link in &record.links {
+                        if config.filter.allows(link) {
+                            frontier.push(link.clone(), record.depth + 1);
+                        }
+                    }
+                }
+            }
+            let mut state = CrawlState::new(&config.start_url, frontier);
+            state.pages = carried;
+            state
+        }
+    };
+    state.save(&config.output_dir)?;
+    let mut paths = PathAllocator::new(&state.pages);
+
+    let mut interrupted = false;
+    loop {
+        let mut batch = Vec::new();
+        while batch.len() < pool.len() && state.fetched < config.max_pages {
+            match state.frontier.pop() {
+                Some((url, depth)) => {
+                    if let Some(robots) = robots {
+                        if !robots.is_allowed(&url).await {
+                            println!("Skipping {} (disallowed by robots.txt)", url);
+                            continue;
+                        }
+                    }
+                    batch.push((url, depth));
+                    state.fetched += 1;
+                }
+                None => break,
+            }
+        }
+        if batch.is_empty() {
+            if state.fetched >= config.max_pages {
+                println!("Reached the limit of {} pages", config.max_pages);
+            }
+            break;
+        }
+
+        let known: Vec<Validators> = batch
+            .iter()
+            .map(|(url, _)| {
+                // A page can only be reused when its markdown is still cached
+                previous
+                    .get(url)
+                    .filter(|page| {
+                        state::cached_page(&config.output_dir, &page.content_hash).is_some()
+                    })
+                    .map(|page| page.validators.clone())
+                    .unwrap_or_default()
+            })
+            .collect();
+        let fetches = join_all(batch.iter().zip(&known).zip(pool).map(
+            |(((url, depth), known), fetcher)| {
+                println!("Fetching content from {} (depth {})", url, depth);
+                fetch_page(fetcher.as_ref(), url, known, profile)
+            },
+        ));
+        // The checkpoint on disk predates this batch, so an interrupted batch
+        // is fetched again on resume.
+        let results = tokio::select! {
+            results = fetches => results,
+            _ = tokio::signal::ctrl_c() => {
+                println!("Interrupted; run again with --resume to continue");
+                interrupted = true;
+                break;
+            }
+        };
+
+        for ((url, depth), result) in batch.into_iter().zip(results) {
+            let (mut record, content) = match result {
+                Ok(Some(page)) => {
+                    let page_url = match page.canonical {
+                        Some(declared)
+                            if canonical::key(&declared) != canonical::key(&url)
+                                && in_scope(&declared, &config.start_url) =>
+                        {
+                            state.frontier.mark_visited(&declared);
+                            declared
+                        }
+                        _ => url.clone(),
+                    };
+                    let record = PageRecord {
+                        url: page_url,
+                        path: String::new(),
+                        title: page.title,
+                        depth,
+                        content_hash: content_hash(&page.content),
+                        validators: page.validators,
+                        links: page
+                            .links
+                            .into_iter()
+                            .filter(|link| in_scope(link, &config.start_url))
+                            .collect(),
+                        anchors: page.anchors,
+                    };
+                    (record, Some(page.content))
+                }
+                Ok(None) => match previous.get(&url) {
+                    Some(old) => {
+                        println!("Not modified since the last crawl: {}", url);
+                        let record = PageRecord {
+                            depth,
+                            ..old.clone()
+                        };
+                        (record, None)
+                    }
+                    None => continue,
+                },
+                Err(e) => {
+                    println!("Error fetching content: {}: {}", url, e);
+                    continue;
+                }
+            };
+
+            if let Some(original) = duplicate_of(&state.pages, &record) {
+                println!("Skipping {} (duplicate of {})", url, original.url);
+                state.aliases.insert(url, original.url.clone());
+                continue;
+            }
+            if record.url != url {
+                println!("{} declares {} as its canonical URL", url, record.url);
+                state.aliases.insert(url, record.url.clone());
+            }
+
+            record.path = paths.assign(
+                &record.url,
+                previous.get(&record.url).map(|old| old.path.as_str()),
+            );
+            if let Some(content) = content {
+                state::cache_page(&config.output_dir, &record.content_hash, &content)?;
+            }
+
+            if depth < config.max_depth {
+                for link in &record.links {
+                    if config.filter.allows(link) {
+                        state.frontier.push(link.clone(), depth + 1);
+                    }
+                }
+            }
+            state.pages.push(record);
+        }
+
+        state.save(&config.output_dir)?;
+    }
+
+    write_pages(&config.output_dir, &state)?;
+    write_index(&config.output_dir, &state.pages)?;
+    write_path_map(&config.output_dir, &state.pages)?;
+    if !interrupted {
+        println!(
+            "Crawl summary: {}",
+            CrawlSummary::compare(&previous, &state.pages)
+        );
+        state::prune_cache(&config.output_dir, &state.pages)?;
+        Manifest { pages: state.pages }.save(&config.output_dir)?;
+        CrawlState::remove(&config.output_dir)?;
+    }
+    Ok(())
+}
+
+/// The profile chosen with `--profile` or in the config file, or else the one
+/// the start page's markup points to, with the config file's rules on top.
+/// The start page is read over plain HTTP, since every supported generator
+/// renders its identifying markup on the server.
+async fn resolve_profile(config: &CrawlConfig, limiter: &Arc<RateLimiter>) -> Profile {
+    let configured = config.site_config.as_ref().and_then(|site| site.profile);
+    let name = match (config.profile, configured) {
+        (ProfileName::Auto, Some(name)) => name,
+        (name, _) => name,
+    };
+    let name = match name {
+        ProfileName::Auto => {
+            let fetcher = PoliteFetcher::new(
+                Box::new(HttpFetcher::new(&config.user_agent)),
+                limiter.clone(),
+                config.max_retries,
+            );
+            match fetcher.fetch_html(&config.start_url).await {
+                Ok(html) => profile::detect(&html),
+                Err(e) => {
+                    println!("Could not detect the site profile: {}", e);
+                    ProfileName::Generic
+                }
+            }
+        }
+        name => name,
+    };
+    let profile = name.profile().unwrap_or_else(|| {
+        ProfileName::Generic
+            .profile()
+            .expect("the generic profile is built in")
+    });
+    match &config.site_config {
+        Some(site) => {
+            println!("Applying the extraction rules from the config file");
+            site.apply(profile)
+        }
+        None => profile,
+    }
+}
+
+/// Loads the checkpoint to continue from when `--resume` is given.
+fn resume_state(config: &CrawlConfig) -> Result<Option<CrawlState>, Crawler> {
+    if !config.resume {
+        return Ok(None);
+    }
+
+    match CrawlState::load(&config.output_dir)? {
+        Some(state) if state.start_url != config.start_url => Err(Crawler::Parsing(format!(
+            "The checkpoint in {} belongs to a crawl of {}",
+            config.output_dir.display(),
+            state.start_url
+        ))),
+        Some(state) => {
+            println!(
+                "Resuming crawl: {} pages written, {} queued",
+                state.pages.len(),
+                state.frontier.len()
+            );
+            Ok(Some(state))
+        }
+        None => {
+            println!(
+                "No checkpoint found in {}; starting a new crawl",
+                config.output_dir.display()
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Queues the in-scope pages listed in the host's sitemaps one level below the
+/// start URL, newest first.
+///
+/// With `--since`, pages last modified before the date are not fetched again.
+/// Their records from the `previous` crawl are returned instead, to be carried
+/// into this one; pages the previous crawl did not keep are queued as usual.
+async fn seed_from_sitemaps(
+    config: &CrawlConfig,
+    frontier: &mut Frontier,
+    previous: &HashMap<String, PageRecord>,
+    limiter: &Arc<RateLimiter>,
+    robots: Option<&RobotsCache>,
+) -> Vec<PageRecord> {
+    // Sitemaps are XML, so they are always read over plain HTTP
+    let fetcher = PoliteFetcher::new(
+        Box::new(HttpFetcher::new(&config.user_agent)),
+        limiter.clone(),
+        config.max_retries,
+    );
+
+    let announced = match (robots, Url::parse(&config.start_url)) {
+        (Some(robots), Ok(start)) => robots.get(&start).await.sitemaps.clone(),
+        _ => Vec::new(),
+    };
+
+    let previous: HashMap<String, &PageRecord> = previous
+        .values()
+        .filter(|page| state::cached_page(&config.output_dir, &page.content_hash).is_some())
+        .map(|page| (canonical::key(&page.url), page))
+        .collect();
+
+    let mut carried = Vec::new();
+    let mut seeded = 0;
+    for entry in sitemap::discover(&fetcher, &config.start_url, &announced).await {
+        if !in_scope(&entry.loc, &config.start_url) || !config.filter.allows(&entry.loc) {
+            continue;
+        }
+        if let Some(since) = &config.since {
+            let key = canonical::key(&entry.loc);
+            if let Some(old) = previous.get(&key) {
+                if !sitemap::modified_since(&entry, since) && frontier.mark_visited(&entry.loc) {
+                    carried.push(PageRecord {
+                        depth: 1,
+                        ..(*old).clone()
+                    });
+                    continue;
+                }
+            }
+        }
+        if frontier.push(entry.loc, 1) {
+            seeded += 1;
+        }
+    }
+
+    println!("Queued {} pages from sitemaps", seeded);
+    if !carried.is_empty() {
+        println!(
+            "Kept {} sitemap pages unchanged since the given date from the last crawl",
+            carried.len()
+        );
+    }
+    carried
+}
+
+/// Finds an already recorded page that `record` duplicates, either because
+/// both resolve to the same canonical URL or because their markdown is identical.
+fn duplicate_of<'a>(pages: &'a [PageRecord], record: &PageRecord) -> Option<&'a PageRecord> {
+    let key = canonical::key(&record.url);
+    let empty = content_hash("");
+    pages.iter().find(|page| {
+        canonical::key(&page.url) == key
+            || (record.content_hash != empty && page.content_hash == record.content_hash)
+    })
+}
+
+/// Writes every page's cached markdown to its output path, with links between
+/// crawled pages made relative, leaving files that already hold the same text
+/// untouched.
+fn write_pages(output_dir: &Path, state: &CrawlState) -> Result<(), Crawler> {
+    let link_map = LinkMap::new(&state.pages, &state.aliases);
+    for page in &state.pages {
+        let Some(content) = state::cached_page(output_dir, &page.content_hash) else {
+            println!("No cached content for {}; skipping", page.url);
+            continue;
+        };
+        let content = link_map.rewrite(&content, &page.path);
+        let content = layout::relocate_images(&content, &page.path);
+
+        let output_path = output_dir.join(&page.path);
+        if fs::read_to_string(&output_path).is_ok_and(|existing| existing == content) {
+            println!("Unchanged: {}", output_path.display());
+            continue;
+        }
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&output_path, content)?;
+        println!("Wrote content to {}", output_path.display());
+    }
+    Ok(())
+}
+
+fn write_index(output_dir: &Path, pages: &[PageRecord]) -> Result<(), Crawler> {
+    let mut index_content = String::new();
+    if let Some(root) = pages.first() {
+        index_content.push_str(&format!("# {}\n\n", root.title));
+    }
+    for page in pages {
+        index_content.push_str(&format!(
+            "{}- [{}]({})\n",
+            "  ".repeat(page.depth),
+            page.title,
+            page.path
+        ));
+    }
+
+    let index_path = output_dir.join(SUMMARY_FILE);
+    fs::write(&index_path, index_content)?;
+    println!("Wrote index to {}", index_path.display());
+    Ok(())
+}
+
+/// Writes the table mapping the title-based filenames of the old flat layout
+/// to the current paths.
+fn write_path_map(output_dir: &Path, pages: &[PageRecord]) -> Result<(), Crawler> {
+    let json = serde_json::to_string_pretty(&layout::path_map(pages))
+        .map_err(|e| Crawler::Parsing(e.to_string()))?;
+    fs::write(output_dir.join(PATH_MAP_FILE), json)?;
+    Ok(())
+}
+
+/// Whether `url` lies under `start_url`: on the same origin, and at the start
+/// URL's path or below it on a segment boundary, so `/docs` admits
+/// `/docs/setup` but not `/docs-old/setup`. Both URLs are normalized first,
+/// so differences in the case of the scheme or host do not matter.
+fn in_scope(url: &str, start_url: &str) -> bool {
+    let (Ok(url), Ok(start)) = (
+        Url::parse(&canonical::normalize(url)),
+        Url::parse(&canonical::normalize(start_url)),
+    ) else {
+        return false;
+    };
+    if url.origin() != start.origin() {
+        return false;
+    }
+    let root = start.path().trim_end_matches('/');
+    let path = url.path();
+    path == root || path.starts_with(&format!("{}/", root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_frontier_is_breadth_first_and_deduplicated() {
+        let mut frontier = Frontier::new("https://example.com/");
+        assert!(frontier.push("https://example.com/a".to_string(), 1));
+        assert!(frontier.push("https://example.com/b".to_string(), 1));
+        assert!(!frontier.push("https://example.com/a".to_string(), 2));
+        assert!(!frontier.push("https://example.com/".to_string(), 1));
+
+        assert_eq!(
+            frontier.pop(),
+            Some(("https://example.com/".to_string(), 0))
+        );
+        assert_eq!(
+            frontier.pop(),
+            Some(("https://example.com/a".to_string(), 1))
+        );
+        assert_eq!(
+            frontier.pop(),
+            Some(("https://example.com/b".to_string(), 1))
+        );
+        assert_eq!(frontier.pop(), None);
+    }
+
+    #[test]
+    fn test_frontier_deduplicates_canonical_urls() {
+        let mut frontier = Frontier::new("https://example.com/docs/");
+        assert!(!frontier.push("https://example.com/docs/index.html".to_string(), 1));
+        assert!(frontier.push("https://example.com/docs/a?utm_source=nav".to_string(), 1));
+        assert!(!frontier.push("https://example.com/docs/a/#usage".to_string(), 1));
+        frontier.mark_visited("https://example.com/docs/b/");
+        assert!(!frontier.push("https://example.com/docs/b".to_string(), 1));
+
+        frontier.pop();
+        assert_eq!(
+            frontier.pop(),
+            Some(("https://example.com/docs/a".to_string(), 1))
+        );
+    }
+
+    #[test]
+    fn test_duplicate_of() {
+        let page = |url: &str, content: &str| PageRecord {
+            url: url.to_string(),
+            title: String::new(),
+            path: String::new(),
+            depth: 1,
+            content_hash: content_hash(content),
+            validators: Validators::default(),
+            links: Vec::new(),
+            anchors: BTreeMap::new(),
+        };
+        let pages = vec![
+            page("https://example.com/docs/a", "# A"),
+            page("https://example.com/docs/empty", ""),
+        ];
+
+        let same_url = page("https://example.com/docs/a/", "# A, edited");
+        assert_eq!(
+            duplicate_of(&pages, &same_url).map(|p| p.url.as_str()),
+            Some("https://example.com/docs/a")
+        );
+        let same_content = page("https://example.com/v2/docs/a", "# A");
+        assert!(duplicate_of(&pages, &same_content).is_some());
+        assert!(duplicate_of(&pages, &page("https://example.com/docs/c", "")).is_none());
+        assert!(duplicate_of(&pages, &page("https://example.com/docs/c", "# C")).is_none());
+    }
+
+    #[test]
+    fn test_in_scope() {
+        assert!(in_scope(
+            "https://example.com/docs/a",
+            "https://example.com/docs/"
+        ));
+        assert!(!in_scope(
+            "https://example.com/blog/a",
+            "https://example.com/docs/"
+        ));
+        assert!(!in_scope(
+            "https://other.com/docs/a",
+            "https://example.com/docs/"
+        ));
+        assert!(in_scope(
+            "https://docs.example.com/guide",
+            "HTTPS://Docs.Example.com/"
+        ));
+        assert!(in_scope(
+            "https://example.com/docs/a",
+            "https://example.com/docs"
+        ));
+        assert!(in_scope(
+            "https://example.com/docs",
+            "https://example.com/docs/"
+        ));
+        assert!(!in_scope(
+            "https://example.com/docs-old/a",
+            "https://example.com/docs"
+        ));
+    }
+}
@@ -0,0 +1,114 @@
+use scraper::Selector;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// A step the browser backend runs in a rendered page before reading it, to
+/// reveal content the page only shows once the reader interacts with it.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Interaction {
+    /// Click every element matching the CSS selector, in document order
+    Click(String),
+    /// Run a JavaScript snippet in the page
+    Script(String),
+}
+
+/// Scrolling through a rendered page before it is read, for pages that only
+/// render content once it scrolls into view.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoScroll {
+    pub enabled: bool,
+    /// Pixels scrolled per step
+    pub step: u32,
+    /// Most steps taken before the page is read as it is
+    pub limit: u32,
+}
+
+impl Default for AutoScroll {
+    fn default() -> Self {
+        AutoScroll {
+            enabled: false,
+            step: 1000,
+            limit: 50,
+        }
+    }
+}
+
+/// How a rendered page is expanded before it is read: scrolled to the bottom
+/// when auto-scroll is on, then the built-in pass that opens collapsed
+/// sections and reveals every tab panel inside the content element, then the
+/// site's own steps in order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expansion {
+    pub scroll: AutoScroll,
+    /// Whether the built-in pass runs
+    pub builtin: bool,
+    /// Content elements the built-in pass is confined to, tried in order;
+    /// the whole body when none matches
+    pub scope: Vec<String>,
+    pub steps: Vec<Interaction>,
+}
+
+impl FromStr for Interaction {
+    type Err = String;
+
+    /// Parses `click:<css>` or `script:<javascript>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(selector) = s.strip_prefix("click:").map(str::trim) {
+            Selector::parse(selector)
+                .map_err(|e| format!("invalid selector {:?}: {:?}", selector, e))?;
+            Ok(Interaction::Click(selector.to_string()))
+        } else if let Some(script) = s.strip_prefix("script:") {
+            Ok(Interaction::Script(script.trim().to_string()))
+        } else {
+            Err(format!(
+                "unknown expansion step {:?}; expected click:<css> or script:<javascript>",
+                s
+            ))
+        }
+    }
+}
+
+impl TryFrom<String> for Interaction {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Interaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interaction::Click(selector) => write!(f, "click:{}", selector),
+            Interaction::Script(script) => write!(f, "script:{}", script),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interaction() {
+        assert_eq!(
+            "click: .tabs > button".parse(),
+            Ok(Interaction::Click(".tabs > button".to_string()))
+        );
+        assert_eq!(
+            "script:document.querySelector('.more').click()".parse(),
+            Ok(Interaction::Script(
+                "document.querySelector('.more').click()".to_string()
+            ))
+        );
+        assert_eq!(
+            Interaction::Click("button.expand".to_string()).to_string(),
+            "click:button.expand"
+        );
+        assert!("hover:.menu".parse::<Interaction>().is_err());
+        assert!("click:div > > p".parse::<Interaction>().is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use crate::browser::{kill_stray_drivers, BrowserSession};
+use crate::expand::Expansion;
+use crate::politeness::parse_retry_after;
+use crate::url_reader::Crawler;
+use crate::wait::PageReady;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// User agent sent when none is configured; its product token is what
+/// robots.txt groups are matched against.
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "web-doc-crawler/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/Soushi888/web-doc-crawler)"
+);
+
+/// Most fetchers a pool may hold; each Firefox session runs its own browser.
+pub const MAX_POOL_SIZE: u64 = 32;
+
+/// Minimum amount of readable text a plain HTTP response must contain before
+/// `--backend auto` trusts it to be a server-rendered page.
+const STATIC_TEXT_THRESHOLD: usize = 200;
+
+/// A way of retrieving pages and assets for the crawler.
+#[async_trait]
+pub trait Fetcher: Send + Sync {
+    /// Returns the HTML of `url` as it should be handed to content extraction.
+    async fn fetch_html(&self, url: &str) -> Result<String, Crawler>;
+
+    /// Returns the raw bytes behind `url`, used for sitemaps and other files.
+    async fn fetch_bytes(&self, url: &str) -> Result<Vec<u8>, Crawler>;
+
+    /// Downloads an image or other asset of at most `max_size` bytes, along
+    /// with its `Content-Type` when the backend knows it.
+    async fn fetch_asset(&self, url: &str, max_size: usize) -> Result<Asset, Crawler> {
+        let bytes = self.fetch_bytes(url).await?;
+        if bytes.len() > max_size {
+            return Err(too_large(url, max_size));
+        }
+        Ok(Asset {
+            bytes,
+            content_type: None,
+        })
+    }
+
+    /// Like [`Fetcher::fetch_html`], but skips the download when the server
+    /// confirms that `known` still describes the current page.
+    ///
+    /// Backends that cannot make conditional requests always fetch.
+    async fn fetch_html_if_modified(
+        &self,
+        url: &str,
+        _known: &Validators,
+    ) -> Result<Conditional, Crawler> {
+        Ok(Conditional::Modified {
+            html: self.fetch_html(url).await?,
+            validators: Validators::default(),
+        })
+    }
+
+    /// Releases any resources held by the backend.
+    async fn close(self: Box<Self>) {}
+}
+
+/// A downloaded asset.
+pub struct Asset {
+    pub bytes: Vec<u8>,
+    /// Media type from the `Content-Type` header, lowercased and without parameters
+    pub content_type: Option<String>,
+}
+
+fn too_large(url: &str, max_size: usize) -> Crawler {
+    Crawler::Parsing(format!("{} is larger than {} bytes", url, max_size))
+}
+
+/// HTTP cache validators remembered for a page between crawls.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// Whether no validator is known, so a conditional request cannot be made.
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    fn from_response(response: &ureq::Response) -> Self {
+        Validators {
+            etag: response.header("ETag").map(str::to_string),
+            last_modified: response.header("Last-Modified").map(str::to_string),
+        }
+    }
+
+    fn apply(&self, mut request: ureq::Request) -> ureq::Request {
+        if let Some(etag) = &self.etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+        request
+    }
+}
+
+pub enum Conditional {
+    Modified {
+        html: String,
+        validators: Validators,
+    },
+    NotModified,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Render pages in headless Firefox through GeckoDriver
+    Browser,
+    /// Fetch pages over plain HTTP, without JavaScript
+    Http,
+    /// Use plain HTTP when the start page is server-rendered, Firefox otherwise
+    Auto,
+}
+
+/// Creates `size` fetchers for `backend`, probing `start_url` when it is [`Backend::Auto`].
+///
+/// Browser sessions wait until a page is `ready` and apply `expansion` to it
+/// before reading it.
+pub async fn connect_pool(
+    backend: Backend,
+    start_url: &str,
+    size: usize,
+    user_agent: &str,
+    ready: &PageReady,
+    expansion: &Expansion,
+) -> Result<Vec<Box<dyn Fetcher>>, Crawler> {
+    let backend = match backend {
+        Backend::Auto => detect_backend(start_url, user_agent).await,
+        other => other,
+    };
+
+    let mut pool: Vec<Box<dyn Fetcher>> = Vec::with_capacity(size);
+    match backend {
+        Backend::Http => {
+            println!("Using the plain HTTP backend with {} workers", size);
+            let fetcher = HttpFetcher::new(user_agent);
+            for _ in 0..size {
+                pool.push(Box::new(fetcher.clone()));
+            }
+        }
+        _ => {
+            println!("Using the Firefox backend with {} sessions", size);
+            kill_stray_drivers().await;
+            for slot in 0..size {
+                match BrowserSession::start(slot, user_agent, ready.clone(), expansion.clone())
+                    .await
+                {
+                    Ok(session) => pool.push(Box::new(session)),
+                    Err(e) => {
+                        close_pool(pool).await;
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(pool)
+}
+
+pub async fn close_pool(pool: Vec<Box<dyn Fetcher>>) {
+    for fetcher in pool {
+        fetcher.close().await;
+    }
+}
+
+async fn detect_backend(start_url: &str, user_agent: &str) -> Backend {
+    match HttpFetcher::new(user_agent).fetch_html(start_url).await {
+        Ok(html) if looks_server_rendered(&html) => Backend::Http,
+        Ok(_) => Backend::Browser,
+        Err(e) => {
+            println!("Could not probe {} over HTTP: {}", start_url, e);
+            Backend::Browser
+        }
+    }
+}
+
+/// Whether a raw HTTP response already carries the page's readable content,
+/// as opposed to an empty shell that JavaScript fills in later.
+fn looks_server_rendered(html: &str) -> bool {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("p, li, pre, h1, h2, h3, td").unwrap();
+
+    let text_len: usize = document
+        .select(&selector)
+        .map(|element| element.text().map(|t| t.trim().len()).sum::<usize>())
+        .sum();
+
+    text_len >= STATIC_TEXT_THRESHOLD
+}
+
+/// Fetches pages with `ureq`, for static sites that need no JavaScript.
+#[derive(Clone)]
+pub struct HttpFetcher {
+    agent: ureq::Agent,
+}
+
+impl HttpFetcher {
+    pub fn new(user_agent: &str) -> Self {
+        HttpFetcher {
+            agent: ureq::AgentBuilder::new().user_agent(user_agent).build(),
+        }
+    }
+
+    fn get(&self, url: &str) -> Result<ureq::Response, Crawler> {
+        Self::call(self.agent.get(url), url)
+    }
+
+    fn call(request: ureq::Request, url: &str) -> Result<ureq::Response, Crawler> {
+        match request.call() {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(status, response)) => Err(Crawler::Http {
+                url: url.to_string(),
+                status,
+                retry_after: response.header("Retry-After").and_then(parse_retry_after),
+            }),
+            Err(e) => Err(Crawler::Network(e.to_string())),
+        }
+    }
+
+    /// Downloads `url`, sending `cookie` as the `Cookie` header when given.
+    ///
+    /// The body is read through a limit, so an oversized asset is abandoned
+    /// after `max_size` bytes rather than held in memory.
+    pub async fn download(
+        &self,
+        url: &str,
+        max_size: usize,
+        cookie: Option<String>,
+    ) -> Result<Asset, Crawler> {
+        let fetcher = self.clone();
+        let url = url.to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut request = fetcher.agent.get(&url);
+            if let Some(cookie) = &cookie {
+                request = request.set("Cookie", cookie);
+            }
+            let response = Self::call(request, &url)?;
+
+            let declared_size = response
+                .header("Content-Length")
+                .and_then(|len| len.parse::<usize>().ok());
+            if declared_size.is_some_and(|len| len > max_size) {
+                return Err(too_large(&url, max_size));
+            }
+            let content_type = response
+                .header("Content-Type")
+                .map(|value| value.split(';').next().unwrap_or("").trim().to_lowercase());
+
+            let mut bytes = Vec::new();
+            response
+                .into_reader()
+                .take((max_size as u64).saturating_add(1))
+                .read_to_end(&mut bytes)?;
+            if bytes.len() > max_size {
+                return Err(too_large(&url, max_size));
+            }
+            Ok(Asset {
+                bytes,
+                content_type,
+            })
+        })
+        .await
+        .map_err(|e| Crawler::Network(e.to_string()))?
+    }
+
+    /// Sends a conditional `HEAD` for `url` and reports whether it changed,
+    /// along with its current validators. Used by backends that render pages
+    /// elsewhere but still want to skip unchanged ones.
+    pub async fn probe(
+        &self,
+        url: &str,
+        known: &Validators,
+    ) -> Result<Option<Validators>, Crawler> {
+        let fetcher = self.clone();
+        let url = url.to_string();
+        let known = known.clone();
+        tokio::task::spawn_blocking(move || {
+            let response = Self::call(known.apply(fetcher.agent.head(&url)), &url)?;
+            if response.status() == 304 {
+                Ok(None)
+            } else {
+                Ok(Some(Validators::from_response(&response)))
+            }
+        })
+        .await
+        .map_err(|e| Crawler::Network(e.to_string()))?
+    }
+}
+
+#[async_trait]
+impl Fetcher for HttpFetcher {
+    async fn fetch_html(&self, url: &str) -> Result<String, Crawler> {
+        let fetcher = self.clone();
+        let url = url.to_string();
+        tokio::task::spawn_blocking(move || {
+            fetcher
+                .get(&url)?
+                .into_string()
+                .map_err(|e| Crawler::Parsing(e.to_string()))
+        })
+        .await
+        .map_err(|e| Crawler::Network(e.to_string()))?
+    }
+
+    async fn fetch_bytes(&self, url: &str) -> Result<Vec<u8>, Crawler> {
+        let fetcher = self.clone();
+        let url = url.to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut bytes = Vec::new();
+            fetcher.get(&url)?.into_reader().read_to_end(&mut bytes)?;
+            Ok(bytes)
+        })
+        .await
+        .map_err(|e| Crawler::Network(e.to_string()))?
+    }
+
+    async fn fetch_asset(&self, url: &str, max_size: usize) -> Result<Asset, Crawler> {
+        self.download(url, max_size, None).await
+    }
+
+    async fn fetch_html_if_modified(
+        &self,
+        url: &str,
+        known: &Validators,
+    ) -> Result<Conditional, Crawler> {
+        let fetcher = self.clone();
+        let url = url.to_string();
+        let known = known.clone();
+        tokio::task::spawn_blocking(move || {
+            let response = Self::call(known.apply(fetcher.agent.get(&url)), &url)?;
+            if response.status() == 304 {
+                return Ok(Conditional::NotModified);
+            }
+            let validators = Validators::from_response(&response);
+            let html = response
+                .into_string()
+                .map_err(|e| Crawler::Parsing(e.to_string()))?;
+            Ok(Conditional::Modified { html, validators })
+        })
+        .await
+        .map_err(|e| Crawler::Network(e.to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_looks_server_rendered() {
+        let shell =
+            r#"<html><body><div id="root"></div><script src="app.js"></script></body></html>"#;
+        assert!(!looks_server_rendered(shell));
+
+        let paragraph = "Installing the toolchain takes a few minutes. ".repeat(10);
+        let page = format!(
+            "<html><body><main><h1>Install</h1><p>{}</p></main></body></html>",
+            paragraph
+        );
+        assert!(looks_server_rendered(&page));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::url_reader::Crawler;
+use regex::Regex;
+use url::Url;
+
+/// Include/exclude rules deciding which discovered URLs are crawled.
+///
+/// Patterns are matched against the URL's path plus query string. A pattern
+/// prefixed with `re:` is a regular expression searched anywhere in it. Any
+/// other pattern is a glob where `*` matches within one path segment and `**`
+/// matches across segments; a glob starting with `/` must match from the start
+/// of the path, while any other glob may match anywhere. Globs only need to
+/// match a prefix, so `/api/` covers everything below `/api/`.
+#[derive(Debug, Default)]
+pub struct UrlFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl UrlFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, Crawler> {
+        Ok(UrlFilter {
+            include: include
+                .iter()
+                .map(|p| compile_pattern(p))
+                .collect::<Result<_, _>>()?,
+            exclude: exclude
+                .iter()
+                .map(|p| compile_pattern(p))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Whether `url` passes the filter: it matches an include pattern (when
+    /// any are given) and no exclude pattern.
+    pub fn allows(&self, url: &str) -> bool {
+        let Ok(parsed) = Url::parse(url) else {
+            return false;
+        };
+        let mut target = parsed.path().to_string();
+        if let Some(query) = parsed.query() {
+            target.push('?');
+            target.push_str(query);
+        }
+
+        (self.include.is_empty() || self.include.iter().any(|re| re.is_match(&target)))
+            && !self.exclude.iter().any(|re| re.is_match(&target))
+    }
+}
+
+fn compile_pattern(pattern: &str) -> Result<Regex, Crawler> {
+    let source = match pattern.strip_prefix("re:") {
+        Some(regex) => regex.to_string(),
+        None => glob_to_regex(pattern),
+    };
+    Regex::new(&source)
+        .map_err(|e| Crawler::Parsing(format!("Invalid URL pattern {:?}: {}", pattern, e)))
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    if glob.starts_with('/') {
+        regex.push('^');
+    }
+
+    let mut rest = glob;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("**") {
+            regex.push_str(".*");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('*') {
+            regex.push_str("[^/]*");
+            rest = after;
+        } else {
+            let next = rest.find('*').unwrap_or(rest.len());
+            regex.push_str(&regex::escape(&rest[..next]));
+            rest = &rest[next..];
+        }
+    }
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> UrlFilter {
+        let owned = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        UrlFilter::new(&owned(include), &owned(exclude)).unwrap()
+    }
+
+    #[test]
+    fn test_exclude_globs() {
+        let filter = filter(&[], &["/blog/", "/changelog/", "?version="]);
+        assert!(filter.allows("https://example.com/docs/intro"));
+        assert!(!filter.allows("https://example.com/blog/2024/release"));
+        assert!(!filter.allows("https://example.com/changelog/"));
+        assert!(!filter.allows("https://example.com/docs/intro?version=2"));
+        assert!(filter.allows("https://example.com/docs/blog/"));
+    }
+
+    #[test]
+    fn test_include_limits_the_crawl() {
+        let filter = filter(&["/api/"], &["/api/*/internal"]);
+        assert!(filter.allows("https://example.com/api/users"));
+        assert!(!filter.allows("https://example.com/guide/users"));
+        assert!(!filter.allows("https://example.com/api/users/internal"));
+        assert!(filter.allows("https://example.com/api/users/v2/internal"));
+    }
+
+    #[test]
+    fn test_double_star_and_regex_patterns() {
+        let filter = filter(&[], &["/docs/**/draft-*", r"re:\.pdf$"]);
+        assert!(!filter.allows("https://example.com/docs/a/b/draft-one"));
+        assert!(filter.allows("https://example.com/docs/a/b/final"));
+        assert!(!filter.allows("https://example.com/files/manual.pdf"));
+    }
+
+    #[test]
+    fn test_invalid_regex_is_reported() {
+        assert!(UrlFilter::new(&["re:(".to_string()], &[]).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use scraper::{ElementRef, Selector};
+use url::Url;
+
+/// Attributes lazy-loading scripts keep the real image URL in until the image
+/// scrolls into view.
+const LAZY_SRC_ATTRS: &[&str] = &[
+    "data-src",
+    "data-lazy-src",
+    "data-original",
+    "data-lazy",
+    "data-url",
+];
+
+/// Attributes holding a `srcset`, lazy-loaded variants first.
+const SRCSET_ATTRS: &[&str] = &["data-srcset", "data-lazy-srcset", "srcset"];
+
+/// Inline images shorter than this are taken to be lazy-loading placeholders
+/// rather than real content.
+const MAX_PLACEHOLDER_DATA_URI: usize = 200;
+
+/// An image found in the page, with the best available source resolved to an
+/// absolute URL (or kept as a `data:` URI).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub src: String,
+    pub alt: String,
+    pub title: Option<String>,
+}
+
+/// One entry of a `srcset`: a URL and the width (`800w`) or pixel density
+/// (`2x`) it is meant for.
+#[derive(Debug, PartialEq)]
+struct Candidate<'a> {
+    url: &'a str,
+    width: Option<f64>,
+    density: f64,
+}
+
+/// Parses a `srcset` attribute. URLs may themselves contain commas, so entries
+/// are split the way browsers do rather than on every comma.
+fn parse_srcset(srcset: &str) -> Vec<Candidate<'_>> {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+
+        let url_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let mut url = &rest[..url_end];
+        rest = &rest[url_end..];
+
+        let mut descriptors = "";
+        if url.ends_with(',') {
+            url = url.trim_end_matches(',');
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            descriptors = &rest[..end];
+            rest = &rest[end..];
+        }
+
+        let mut candidate = Candidate {
+            url,
+            width: None,
+            density: 1.0,
+        };
+        // Descriptors that are not a positive number, such as `NaNx`, are ignored
+        let positive = |value: &str| {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite() && *value > 0.0)
+        };
+        for descriptor in descriptors.split_whitespace() {
+            if let Some(width) = descriptor.strip_suffix('w') {
+                candidate.width = positive(width);
+            } else if let Some(density) = descriptor.strip_suffix('x') {
+                candidate.density = positive(density).unwrap_or(1.0);
+            }
+        }
+        if !url.is_empty() {
+            candidates.push(candidate);
+        }
+    }
+    candidates
+}
+
+/// The largest candidate of a `srcset`: the widest when widths are given,
+/// otherwise the highest pixel density.
+fn best_candidate(srcset: &str) -> Option<&str> {
+    let candidates = parse_srcset(srcset);
+    let widest = candidates
+        .iter()
+        .filter_map(|c| Some((c, c.width?)))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(c, _)| c);
+    let densest = candidates
+        .iter()
+        .max_by(|a, b| a.density.total_cmp(&b.density));
+    widest.or(densest).map(|c| c.url)
+}
+
+/// Whether `src` is a stand-in shown until a lazy-loading script swaps in the
+/// real image.
+fn is_placeholder(src: &str) -> bool {
+    let src = src.trim();
+    if src.is_empty() || src == "about:blank" || src == "#" {
+        return true;
+    }
+    if src.starts_with("data:") {
+        return src.len() < MAX_PLACEHOLDER_DATA_URI;
+    }
+    let name = src
+        .split(['?', '#'])
+        .next()
+        .unwrap_or(src)
+        .rsplit('/')
+        .next()
+        .unwrap_or(src)
+        .to_lowercase();
+    name.contains("placeholder")
+        || name.contains("spacer")
+        || name.contains("blank.")
+        || name.contains("transparent.")
+        || name.contains("pixel.")
+        || name.contains("1x1")
+}
+
+/// The best `srcset` candidate among `attrs` on `element`, if any.
+fn srcset_source(element: &ElementRef, attrs: &[&str]) -> Option<String> {
+    attrs
+        .iter()
+        .filter_map(|attr| element.value().attr(attr))
+        .filter_map(best_candidate)
+        .find(|url| !is_placeholder(url))
+        .map(str::to_string)
+}
+
+/// The `<source>` a browser would most likely pick inside a `<picture>`,
+/// preferring sources that apply regardless of media queries.
+fn picture_source(picture: &ElementRef) -> Option<String> {
+    let selector = Selector::parse("source").unwrap();
+    let sources: Vec<ElementRef> = picture
+        .select(&selector)
+        .filter(|source| {
+            source
+                .value()
+                .attr("type")
+                .is_none_or(|t| t.starts_with("image/"))
+        })
+        .collect();
+    sources
+        .iter()
+        .filter(|source| source.value().attr("media").is_none())
+        .chain(sources.iter())
+        .find_map(|source| srcset_source(source, SRCSET_ATTRS))
+}
+
+/// Resolves the image shown by an `<img>` element, or `None` when it has no
+/// source but a placeholder.
+///
+/// Sources are tried from the most to the least detailed: an enclosing
+/// `<picture>`'s `<source>`, the `<img>`'s own `srcset` (or its lazy-loaded
+/// variant), the lazy-loading `data-*` attributes, and finally `src`.
+pub fn resolve_image(img: &ElementRef, base_url: &str) -> Option<Image> {
+    let picture = img
+        .parent()
+        .and_then(ElementRef::wrap)
+        .filter(|parent| parent.value().name() == "picture");
+
+    let src = picture
+        .as_ref()
+        .and_then(picture_source)
+        .or_else(|| srcset_source(img, SRCSET_ATTRS))
+        .or_else(|| {
+            LAZY_SRC_ATTRS
+                .iter()
+                .chain(["src"].iter())
+                .filter_map(|attr| img.value().attr(attr))
+                .find(|src| !is_placeholder(src))
+                .map(str::to_string)
+        })?;
+
+    Some(Image {
+        src: absolute_src(src.trim(), base_url),
+        alt: img.value().attr("alt").unwrap_or("").trim().to_string(),
+        title: img
+            .value()
+            .attr("title")
+            .map(str::trim)
+            .filter(|title| !title.is_empty())
+            .map(str::to_string),
+    })
+}
+
+impl Image {
+    /// Markdown showing this image from `src`.
+    pub fn to_markdown(&self, src: &str) -> String {
+        let alt = self
+            .alt
+            .replace('\\', "\\\\")
+            .replace('[', "\\[")
+            .replace(']', "\\]");
+        let src = src
+            .replace(' ', "%20")
+            .replace('(', "%28")
+            .replace(')', "%29");
+        let title = self
+            .title
+            .as_ref()
+            .map(|title| format!(" \"{}\"", title.replace('"', "\\\"")))
+            .unwrap_or_default();
+        format!("![{}]({}{})", alt, src, title)
+    }
+}
+
+fn absolute_src(src: &str, base_url: &str) -> String {
+    if src.starts_with("data:") {
+        return src.to_string();
+    }
+    Url::parse(base_url)
+        .and_then(|base| base.join(src))
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| src.to_string())
+}
+
+/// Finds every image under `element`. Each entry pairs the element to replace
+/// (the `<picture>` when there is one, otherwise the `<img>`) with the image
+/// it shows, or `None` for placeholders with nothing behind them.
+pub fn find_images<'a>(
+    element: &ElementRef<'a>,
+    base_url: &str,
+) -> Vec<(ElementRef<'a>, Option<Image>)> {
+    let selector = Selector::parse("img").unwrap();
+    element
+        .select(&selector)
+        .map(|img| {
+            let outer = img
+                .parent()
+                .and_then(ElementRef::wrap)
+                .filter(|parent| parent.value().name() == "picture")
+                .unwrap_or(img);
+            (outer, resolve_image(&img, base_url))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scraper::Html;
+
+    const BASE: &str = "https://example.com/docs/page";
+
+    fn first_image(html: &str) -> Option<Image> {
+        let document = Html::parse_fragment(html);
+        find_images(&document.root_element(), BASE)
+            .into_iter()
+            .next()
+            .and_then(|(_, image)| image)
+    }
+
+    #[test]
+    fn test_parse_srcset() {
+        let candidates =
+            parse_srcset("small.png 480w, https://cdn.example.com/w_800,h_600/big.png 800w");
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(
+            candidates[1].url,
+            "https://cdn.example.com/w_800,h_600/big.png"
+        );
+        assert_eq!(candidates[1].width, Some(800.0));
+
+        assert_eq!(best_candidate("a.png, b.png 2x, c.png 1.5x"), Some("b.png"));
+        assert_eq!(best_candidate("a.png 1x,b.png 400w"), Some("b.png"));
+        assert_eq!(best_candidate(""), None);
+        assert_eq!(best_candidate("a.png NaNx, b.png 2x"), Some("b.png"));
+        assert_eq!(best_candidate("a.png NaNw, b.png 400w"), Some("b.png"));
+        assert_eq!(best_candidate("a.png -1w, b.png 1.5x"), Some("b.png"));
+    }
+
+    #[test]
+    fn test_srcset_beats_src() {
+        let image = first_image(
+            r#"<img src="logo.png" srcset="logo.png 1x, logo@2x.png 2x" alt=" Logo " title="The logo">"#,
+        )
+        .unwrap();
+        assert_eq!(image.src, "https://example.com/docs/logo@2x.png");
+        assert_eq!(image.alt, "Logo");
+        assert_eq!(image.title.as_deref(), Some("The logo"));
+    }
+
+    #[test]
+    fn test_lazy_loaded_image() {
+        let image = first_image(
+            r#"<img src="data:image/gif;base64,R0lGODlhAQABAAAAACw=" data-src="/img/diagram.svg" loading="lazy" alt="Diagram">"#,
+        )
+        .unwrap();
+        assert_eq!(image.src, "https://example.com/img/diagram.svg");
+
+        let image = first_image(
+            r#"<img src="/assets/placeholder.png" data-srcset="/img/a-400.png 400w, /img/a-1200.png 1200w">"#,
+        )
+        .unwrap();
+        assert_eq!(image.src, "https://example.com/img/a-1200.png");
+
+        assert_eq!(first_image(r#"<img src="/assets/spacer.gif">"#), None);
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let image = Image {
+            src: "https://example.com/a.png".to_string(),
+            alt: "Step [1]".to_string(),
+            title: Some("The \"first\" step".to_string()),
+        };
+        assert_eq!(
+            image.to_markdown("images/a (1).png"),
+            r#"![Step \[1\]](images/a%20%281%29.png "The \"first\" step")"#
+        );
+    }
+
+    #[test]
+    fn test_picture_sources() {
+        let html = r#"<picture>
+            <source media="(max-width: 600px)" srcset="/img/narrow.webp">
+            <source type="image/webp" srcset="/img/wide.webp 1x, /img/wide@2x.webp 2x">
+            <img src="/img/wide.jpg" alt="Screenshot">
+        </picture>"#;
+        let document = Html::parse_fragment(html);
+        let images = find_images(&document.root_element(), BASE);
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].0.value().name(), "picture");
+        let image = images[0].1.as_ref().unwrap();
+        assert_eq!(image.src, "https://example.com/img/wide@2x.webp");
+        assert_eq!(image.alt, "Screenshot");
+    }
+}
@@ -0,0 +1,305 @@
+use crate::markdown;
+use crate::state::PageRecord;
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use std::collections::HashMap;
+use url::Url;
+
+/// Name of the table of contents written next to the pages.
+pub const SUMMARY_FILE: &str = "SUMMARY.md";
+
+/// Directory downloaded images are written to, which pages never go into.
+pub const IMAGES_DIR: &str = "images";
+
+/// Name of the table mapping the old title-based filenames to the new paths.
+pub const PATH_MAP_FILE: &str = "path-map.json";
+
+/// Extensions dropped from the last path segment before `.md` is added.
+const PAGE_EXTENSIONS: &[&str] = &[".html", ".htm", ".php", ".aspx", ".md"];
+
+/// Maps a page URL onto a markdown path mirroring the site's hierarchy, so
+/// `/guides/setup/install` becomes `guides/setup/install.md`.
+///
+/// Directory index pages take the directory's name (`/guides/` and
+/// `/guides/index.html` are both `guides.md`), the site root is `index.md`, and
+/// a query string is appended to the filename after an underscore.
+pub fn url_to_path(url: &str) -> String {
+    let Ok(parsed) = Url::parse(url) else {
+        return "index.md".to_string();
+    };
+
+    let mut segments: Vec<String> = parsed
+        .path_segments()
+        .map(|segments| {
+            segments
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| sanitize_segment(&percent_decode_str(segment).decode_utf8_lossy()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if let Some(last) = segments.last_mut() {
+        for extension in PAGE_EXTENSIONS {
+            if let Some(stem) = last.strip_suffix(extension) {
+                if !stem.is_empty() {
+                    *last = stem.to_string();
+                }
+                break;
+            }
+        }
+        if last == "index" {
+            segments.pop();
+        }
+    }
+
+    let mut stem = if segments.is_empty() {
+        "index".to_string()
+    } else {
+        segments.join("/")
+    };
+    if let Some(query) = parsed.query().filter(|query| !query.is_empty()) {
+        stem.push('_');
+        stem.push_str(&sanitize_segment(
+            &percent_decode_str(query).decode_utf8_lossy(),
+        ));
+    }
+    format!("{}.md", stem)
+}
+
+/// Keeps a path segment safe to use as a file or directory name.
+fn sanitize_segment(segment: &str) -> String {
+    let cleaned: String = segment
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    // Leading dots would make `..` or hidden files
+    let cleaned = cleaned.trim_start_matches('.');
+    if cleaned.is_empty() {
+        "-".to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+/// Hands out a unique output path to every page of a crawl.
+///
+/// A page gets the path [`url_to_path`] gives its URL. When another URL
+/// already holds it, the page gets the first free `-2`, `-3`, ... variant, so
+/// with the crawl's fixed processing order the same site always yields the
+/// same paths. Paths are compared case-insensitively to stay distinct on
+/// case-insensitive filesystems. Pages under [`IMAGES_DIR`] move to its `-2`
+/// variant, as pages named like [`SUMMARY_FILE`] do.
+#[derive(Default)]
+pub struct PathAllocator {
+    taken: HashMap<String, String>,
+}
+
+impl PathAllocator {
+    /// Starts from the paths of pages that are already recorded.
+    pub fn new(pages: &[PageRecord]) -> Self {
+        let mut allocator = PathAllocator::default();
+        allocator.reserve(SUMMARY_FILE, "");
+        for page in pages {
+            allocator.reserve(&page.path, &page.url);
+        }
+        allocator
+    }
+
+    fn reserve(&mut self, path: &str, url: &str) {
+        self.taken.insert(path.to_lowercase(), url.to_string());
+    }
+
+    fn is_free(&self, path: &str, url: &str) -> bool {
+        self.taken
+            .get(&path.to_lowercase())
+            .is_none_or(|owner| owner == url)
+    }
+
+    /// Assigns the output path of `url`. `previous` is the path the page had in
+    /// the last crawl; it is kept when it is still a valid variant, so that a
+    /// collision suffix does not move between recrawls.
+    pub fn assign(&mut self, url: &str, previous: Option<&str>) -> String {
+        let mut base = url_to_path(url);
+        if let Some((dir, rest)) = base.split_once('/') {
+            if dir.eq_ignore_ascii_case(IMAGES_DIR) {
+                base = format!("{}-2/{}", dir, rest);
+            }
+        }
+        let stem = base.trim_end_matches(".md");
+
+        if let Some(previous) = previous {
+            let is_variant = previous == base
+                || previous
+                    .strip_prefix(stem)
+                    .and_then(|rest| rest.strip_prefix('-'))
+                    .and_then(|rest| rest.strip_suffix(".md"))
+                    .is_some_and(|n| n.parse::<usize>().is_ok_and(|n| n >= 2));
+            if is_variant && self.is_free(previous, url) {
+                self.reserve(previous, url);
+                return previous.to_string();
+            }
+        }
+
+        let mut path = base.clone();
+        let mut n = 2;
+        while !self.is_free(&path, url) {
+            path = format!("{}-{}.md", stem, n);
+            n += 1;
+        }
+        self.reserve(&path, url);
+        path
+    }
+}
+
+/// One row of the table mapping the flat, title-based filenames written by
+/// earlier versions to the current paths.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct PathMapping {
+    pub url: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// Builds the old-to-new path table, ordered by new path.
+pub fn path_map(pages: &[PageRecord]) -> Vec<PathMapping> {
+    let mut mappings: Vec<PathMapping> = pages
+        .iter()
+        .map(|page| PathMapping {
+            url: page.url.clone(),
+            old: format!("{}.md", legacy_filename(&page.title)),
+            new: page.path.clone(),
+        })
+        .collect();
+    mappings.sort_by(|a, b| a.new.cmp(&b.new));
+    mappings
+}
+
+/// The filename stem the flat layout derived from a page's title.
+fn legacy_filename(title: &str) -> String {
+    title.replace(|c: char| !c.is_alphanumeric(), "-")
+}
+
+/// Prefix that leads from the directory of `path` back to the output root,
+/// such as `../../` for `guides/setup/install.md`.
+pub fn root_prefix(path: &str) -> String {
+    "../".repeat(path.matches('/').count())
+}
+
+/// Points the `images/` references of markdown extracted for the output root
+/// at the same files from the page's own directory, in markdown images as
+/// well as in tables kept as HTML. Code is left untouched.
+pub fn relocate_images(content: &str, path: &str) -> String {
+    let prefix = root_prefix(path);
+    if prefix.is_empty() {
+        return content.to_string();
+    }
+    markdown::rewrite_prose(content, |prose| {
+        prose
+            .replace("](images/", &format!("]({}images/", prefix))
+            .replace("src=\"images/", &format!("src=\"{}images/", prefix))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_to_path() {
+        let cases = [
+            ("https://example.com/", "index.md"),
+            ("https://example.com/index.html", "index.md"),
+            (
+                "https://example.com/guides/setup/install",
+                "guides/setup/install.md",
+            ),
+            ("https://example.com/guides/", "guides.md"),
+            ("https://example.com/guides/index.html", "guides.md"),
+            ("https://example.com/api/client.html", "api/client.md"),
+            ("https://example.com/api?version=2", "api_version-2.md"),
+            ("https://example.com/caf%C3%A9/a%20b", "café/a-b.md"),
+            ("https://example.com/.well-known/a", "well-known/a.md"),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(url_to_path(url), expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_allocator_resolves_collisions_deterministically() {
+        let mut allocator = PathAllocator::new(&[]);
+        assert_eq!(
+            allocator.assign("https://example.com/guide/", None),
+            "guide.md"
+        );
+        assert_eq!(
+            allocator.assign("https://example.com/guide.html", None),
+            "guide-2.md"
+        );
+        assert_eq!(
+            allocator.assign("https://example.com/Guide", None),
+            "Guide-3.md"
+        );
+        assert_eq!(
+            allocator.assign("https://example.com/guide/", None),
+            "guide.md"
+        );
+        assert_eq!(
+            allocator.assign("https://example.com/summary", None),
+            "summary-2.md"
+        );
+        assert_eq!(
+            allocator.assign("https://example.com/images/formats", None),
+            "images-2/formats.md"
+        );
+        assert_eq!(
+            allocator.assign("https://example.com/images", None),
+            "images.md"
+        );
+    }
+
+    #[test]
+    fn test_allocator_keeps_previous_variant() {
+        let mut allocator = PathAllocator::new(&[]);
+        assert_eq!(
+            allocator.assign("https://example.com/guide.html", Some("guide-2.md")),
+            "guide-2.md"
+        );
+        assert_eq!(
+            allocator.assign("https://example.com/guide/", None),
+            "guide.md"
+        );
+        // A title-based path from the flat layout is not kept
+        assert_eq!(
+            allocator.assign("https://example.com/setup", Some("Setup-Guide.md")),
+            "setup.md"
+        );
+    }
+
+    #[test]
+    fn test_root_prefix() {
+        assert_eq!(root_prefix("index.md"), "");
+        assert_eq!(root_prefix("guides/setup/install.md"), "../../");
+        assert_eq!(
+            relocate_images("![Logo](images/ab12.png)", "guides/setup.md"),
+            "![Logo](../images/ab12.png)"
+        );
+        assert_eq!(
+            relocate_images("<td><img src=\"images/ab12.png\" alt=\"\"></td>", "a/b.md"),
+            "<td><img src=\"../images/ab12.png\" alt=\"\"></td>"
+        );
+        assert_eq!(
+            relocate_images(
+                "Use `![x](images/a.png)`:\n\n```md\n![x](images/a.png)\n```\n",
+                "a/b.md"
+            ),
+            "Use `![x](images/a.png)`:\n\n```md\n![x](images/a.png)\n```\n"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use crate::canonical;
+use crate::markdown;
+use crate::state::PageRecord;
+use regex::{Captures, Regex};
+use scraper::{ElementRef, Selector};
+use std::collections::{BTreeMap, HashMap};
+use url::Url;
+
+/// Generates the anchor a markdown renderer gives a heading, the way GitHub
+/// does: lowercase, punctuation dropped and spaces turned into hyphens.
+pub fn slugify(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Maps the HTML ids of the headings inside `elements`, the content elements
+/// a page is converted from, to the slugs the same headings get in markdown.
+///
+/// An id may sit on the heading itself or on an anchor inside it. Repeated
+/// heading texts get `-1`, `-2`, ... suffixes in document order, as renderers do.
+pub fn heading_anchors(elements: &[ElementRef<'_>]) -> BTreeMap<String, String> {
+    let heading_selector = Selector::parse("h1, h2, h3, h4, h5, h6").unwrap();
+    let anchor_selector = Selector::parse("[id], a[name]").unwrap();
+
+    let mut anchors = BTreeMap::new();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for heading in elements
+        .iter()
+        .flat_map(|element| element.select(&heading_selector))
+    {
+        let text: String = heading.text().collect();
+        let base = slugify(&text);
+        let count = counts.entry(base.clone()).or_insert(0);
+        let slug = if *count == 0 {
+            base.clone()
+        } else {
+            format!("{}-{}", base, count)
+        };
+        *count += 1;
+
+        let ids = heading.value().attr("id").into_iter().chain(
+            heading.select(&anchor_selector).filter_map(|anchor| {
+                anchor
+                    .value()
+                    .attr("id")
+                    .or_else(|| anchor.value().attr("name"))
+            }),
+        );
+        for id in ids {
+            anchors.insert(id.to_string(), slug.clone());
+        }
+    }
+    anchors
+}
+
+/// Where each crawled URL was written, used to turn absolute links between
+/// crawled pages into relative markdown links.
+pub struct LinkMap<'a> {
+    pages: HashMap<String, &'a PageRecord>,
+}
+
+impl<'a> LinkMap<'a> {
+    /// Indexes `pages` by canonical URL; every alias resolves to the page it
+    /// duplicates.
+    pub fn new(pages: &'a [PageRecord], aliases: &BTreeMap<String, String>) -> Self {
+        let mut by_key: HashMap<String, &PageRecord> = pages
+            .iter()
+            .map(|page| (canonical::key(&page.url), page))
+            .collect();
+        for (alias, target) in aliases {
+            if let Some(page) = by_key.get(&canonical::key(target)).copied() {
+                by_key.entry(canonical::key(alias)).or_insert(page);
+            }
+        }
+        LinkMap { pages: by_key }
+    }
+
+    /// Rewrites the links of the page written to `from_path`: markdown links,
+    /// and the `href`s of tables kept as HTML. Code is left untouched.
+    ///
+    /// Links to crawled pages become paths relative to `from_path`'s
+    /// directory, with fragments mapped to heading slugs. Links to pages that
+    /// were not crawled stay absolute.
+    pub fn rewrite(&self, content: &str, from_path: &str) -> String {
+        let link_regex = Regex::new(r#"\]\(([^)\s]+)(\s+"(?:[^"\\]|\\.)*")?\)"#).unwrap();
+        let href_regex = Regex::new(r#"<a href="([^"]+)""#).unwrap();
+        markdown::rewrite_prose(content, |prose| {
+            let prose = link_regex.replace_all(prose, |caps: &Captures| {
+                let target = &caps[1];
+                let title = caps.get(2).map_or("", |m| m.as_str());
+                match self.resolve(target, from_path) {
+                    Some(resolved) => format!("]({}{})", resolved, title),
+                    None => caps[0].to_string(),
+                }
+            });
+            href_regex
+                .replace_all(&prose, |caps: &Captures| {
+                    match self.resolve(&caps[1].replace("&amp;", "&"), from_path) {
+                        Some(resolved) => format!("<a href=\"{}\"", resolved),
+                        None => caps[0].to_string(),
+                    }
+                })
+                .into_owned()
+        })
+    }
+
+    fn resolve(&self, target: &str, from_path: &str) -> Option<String> {
+        let url = Url::parse(target).ok()?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+        let page = self.pages.get(&canonical::key(url.as_str()))?;
+
+        let fragment = url.fragment().filter(|f| !f.is_empty()).map(|fragment| {
+            page.anchors
+                .get(fragment)
+                .cloned()
+                .unwrap_or_else(|| fragment.to_string())
+        });
+        match fragment {
+            Some(fragment) if page.path == from_path => Some(format!("#{}", fragment)),
+            Some(fragment) => Some(format!(
+                "{}#{}",
+                relative_path(from_path, &page.path),
+                fragment
+            )),
+            None => Some(relative_path(from_path, &page.path)),
+        }
+    }
+}
+
+/// The path of `to` relative to the directory of `from`, both given relative
+/// to the output root.
+pub fn relative_path(from: &str, to: &str) -> String {
+    let from_dirs: Vec<&str> = from.split('/').collect();
+    let from_dirs = &from_dirs[..from_dirs.len() - 1];
+    let to_parts: Vec<&str> = to.split('/').collect();
+
+    let common = from_dirs
+        .iter()
+        .zip(&to_parts)
+        .take_while(|(a, b)| a == b)
+        .count()
+        .min(to_parts.len() - 1);
+
+    let mut parts: Vec<&str> = vec![".."; from_dirs.len() - common];
+    parts.extend(&to_parts[common..]);
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::Validators;
+    use scraper::Html;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Getting Started"), "getting-started");
+        assert_eq!(slugify(" What's new in v2.0? "), "whats-new-in-v20");
+        assert_eq!(slugify("snake_case & more"), "snake_case--more");
+    }
+
+    #[test]
+    fn test_heading_anchors() {
+        let html = r#"
+            <nav><h2 id="menu">Options</h2></nav>
+            <main>
+                <h2 id="install">Installation guide</h2>
+                <h3><a name="opts"></a>Options</h3>
+                <h3 id="opts-2">Options</h3>
+                <p id="not-a-heading">Text</p>
+            </main>
+        "#;
+        let document = Html::parse_document(html);
+        let main = document
+            .select(&Selector::parse("main").unwrap())
+            .collect::<Vec<_>>();
+        let anchors = heading_anchors(&main);
+        assert_eq!(anchors["install"], "installation-guide");
+        assert_eq!(anchors["opts"], "options");
+        assert_eq!(anchors["opts-2"], "options-1");
+        assert!(!anchors.contains_key("not-a-heading"));
+        assert!(!anchors.contains_key("menu"));
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path("index.md", "guides/setup.md"),
+            "guides/setup.md"
+        );
+        assert_eq!(
+            relative_path("guides/setup/install.md", "guides/intro.md"),
+            "../intro.md"
+        );
+        assert_eq!(
+            relative_path("guides/setup/install.md", "api/client.md"),
+            "../../api/client.md"
+        );
+        assert_eq!(relative_path("guides/a.md", "guides/b.md"), "b.md");
+        assert_eq!(relative_path("guides/a.md", "guides.md"), "../guides.md");
+    }
+
+    fn page(url: &str, path: &str) -> PageRecord {
+        PageRecord {
+            url: url.to_string(),
+            title: String::new(),
+            path: path.to_string(),
+            depth: 0,
+            content_hash: String::new(),
+            validators: Validators::default(),
+            links: Vec::new(),
+            anchors: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let mut install = page(
+            "https://example.com/guides/setup/install",
+            "guides/setup/install.md",
+        );
+        install
+            .anchors
+            .insert("req".to_string(), "requirements".to_string());
+        let pages = vec![page("https://example.com/guides/", "guides.md"), install];
+        let aliases = BTreeMap::from([(
+            "https://example.com/v1/install".to_string(),
+            "https://example.com/guides/setup/install".to_string(),
+        )]);
+        let map = LinkMap::new(&pages, &aliases);
+
+        let content = "See [the guide](https://example.com/guides \"Guides\"), \
+            [requirements](https://example.com/guides/setup/install/#req), \
+            [old docs](https://example.com/v1/install), \
+            [elsewhere](https://other.com/guides/) and ![logo](images/logo.png).";
+        assert_eq!(
+            map.rewrite(content, "guides/intro.md"),
+            "See [the guide](../guides.md \"Guides\"), \
+            [requirements](setup/install.md#requirements), \
+            [old docs](setup/install.md), \
+            [elsewhere](https://other.com/guides/) and ![logo](images/logo.png)."
+        );
+        assert_eq!(
+            map.rewrite(
+                "[up](https://example.com/guides/setup/install#req)",
+                "guides/setup/install.md"
+            ),
+            "[up](#requirements)"
+        );
+        assert_eq!(
+            map.rewrite(
+                "<td><a href=\"https://example.com/guides/\">Guides</a></td>",
+                "guides/intro.md"
+            ),
+            "<td><a href=\"../guides.md\">Guides</a></td>"
+        );
+        let code =
+            "`[a](https://example.com/guides/)`\n\n```md\n[a](https://example.com/guides/)\n```\n";
+        assert_eq!(map.rewrite(code, "guides/intro.md"), code);
+    }
+}
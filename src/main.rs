@@ -1,10 +1,34 @@
 use clap::Parser;
+use config::SiteConfig;
+use expand::Interaction;
+use fetcher::{Backend, DEFAULT_USER_AGENT, MAX_POOL_SIZE};
+use filter::UrlFilter;
+use markdown::AdmonitionStyle;
+use profile::ProfileName;
 use std::error::Error;
 use std::fs;
 use std::path::PathBuf;
-use url_reader::{extract_links, fetch_url_with_firefox};
-
+use std::time::Duration;
+use wait::WaitStrategy;
+
+mod browser;
+mod canonical;
+mod config;
+mod crawler;
+mod expand;
+mod fetcher;
+mod filter;
+mod images;
+mod layout;
+mod links;
+mod markdown;
+mod politeness;
+mod profile;
+mod robots;
+mod sitemap;
+mod state;
 mod url_reader;
+mod wait;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -24,12 +48,98 @@ struct Args {
     /// Maximum depth to crawl
     #[arg(short, long, default_value_t = 3)]
     depth: usize,
+
+    /// How pages are fetched
+    #[arg(short, long, value_enum, default_value_t = Backend::Auto)]
+    backend: Backend,
+
+    /// Number of pages fetched at the same time (1 to 32)
+    #[arg(
+        short,
+        long,
+        default_value_t = 4,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=MAX_POOL_SIZE)
+    )]
+    concurrency: usize,
+
+    /// Maximum requests per second sent to any single host (0 disables the limit)
+    #[arg(long, default_value_t = 2.0)]
+    rate: f64,
+
+    /// How many times a request answered with 429 or 5xx is retried
+    #[arg(long, default_value_t = 3)]
+    max_retries: u32,
+
+    /// User agent sent with every request and matched against robots.txt
+    #[arg(long, default_value = DEFAULT_USER_AGENT)]
+    user_agent: String,
+
+    /// Do not fetch or obey robots.txt (only for sites you own)
+    #[arg(long)]
+    ignore_robots: bool,
+
+    /// Do not seed the crawl from sitemap.xml
+    #[arg(long)]
+    no_sitemap: bool,
+
+    /// Skip sitemap pages whose <lastmod> is older than this date (YYYY-MM-DD)
+    #[arg(long, value_parser = sitemap::parse_date)]
+    since: Option<String>,
+
+    /// Continue the interrupted crawl checkpointed in the output directory
+    #[arg(long)]
+    resume: bool,
+
+    /// Only crawl URLs whose path matches this pattern (repeatable)
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Never crawl URLs whose path matches this pattern (repeatable)
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Documentation generator the site is built with
+    #[arg(long, value_enum, default_value_t = ProfileName::Auto)]
+    profile: ProfileName,
+
+    /// TOML file with extraction rules for the site
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// What the browser waits for before reading a page: load, network-idle,
+    /// dom-stable or selector:<css> (repeatable; default: the profile's)
+    #[arg(long)]
+    wait: Vec<WaitStrategy>,
+
+    /// Seconds the browser waits for a page to be ready before reading it anyway
+    #[arg(long, default_value_t = 15)]
+    wait_timeout: u64,
+
+    /// Do not open collapsed sections, accordions and tab panels before
+    /// reading a rendered page
+    #[arg(long)]
+    no_expand: bool,
+
+    /// Extra step run in a rendered page before reading it: click:<css> or
+    /// script:<javascript> (repeatable, run in order after the profile's)
+    #[arg(long)]
+    expand: Vec<Interaction>,
+
+    /// Scroll rendered pages to the bottom before reading them, for pages that
+    /// render content as it scrolls into view (default: the profile's setting)
+    #[arg(long)]
+    scroll: bool,
+
+    /// How notes, tips and warnings are written (default: gfm)
+    #[arg(long, value_enum)]
+    admonitions: Option<AdmonitionStyle>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
     let url = args.url;
+    let site_config = args.config.as_deref().map(SiteConfig::load).transpose()?;
     let output_dir = PathBuf::from(args.output.unwrap_or_else(|| "docs".to_string()));
 
     // Set output directory
@@ -46,54 +156,31 @@ async fn main() -> Result<(), Box<dyn Error>> {
         fs::create_dir_all(&images_dir)?;
     }
 
-    // Process initial URL
-    println!("Fetching content from {}", url);
-    match fetch_url_with_firefox(&url).await {
-        Ok(page) => {
-            let filename = sanitize_filename(&page.title);
-            let output_path = output_dir.join(format!("{}.md", filename));
-
-            // Write content to file
-            fs::write(&output_path, &page.content)?;
-            println!("Wrote content to {}", output_path.display());
-
-            // Add to index
-            let mut index_content = String::new();
-            index_content.push_str(&format!("# {}\n\n", page.title));
-            index_content.push_str(&format!("- [{}]({}.md)\n", page.title, filename));
-
-            // Extract and process links
-            let links = extract_links(&url)?;
-            for link in links {
-                println!("Processing link: {}", link);
-                match fetch_url_with_firefox(&link).await {
-                    Ok(sub_page) => {
-                        let sub_filename = sanitize_filename(&sub_page.title);
-                        let sub_output_path = output_dir.join(format!("{}.md", sub_filename));
-
-                        // Write content to file
-                        fs::write(&sub_output_path, &sub_page.content)?;
-                        println!("Wrote content to {}", sub_output_path.display());
-
-                        // Add to index
-                        index_content
-                            .push_str(&format!("  - [{}]({}.md)\n", sub_page.title, sub_filename));
-                    }
-                    Err(e) => println!("Error fetching content: {}: {}", link, e),
-                }
-            }
-
-            // Write index file
-            let index_path = output_dir.join("index.md");
-            fs::write(&index_path, index_content)?;
-            println!("Wrote index to {}", index_path.display());
-        }
-        Err(e) => println!("Error fetching content: {}: {}", url, e),
-    }
+    crawler::crawl(&crawler::CrawlConfig {
+        start_url: url,
+        output_dir,
+        max_pages: args.max_pages,
+        max_depth: args.depth,
+        backend: args.backend,
+        concurrency: args.concurrency,
+        requests_per_second: args.rate,
+        max_retries: args.max_retries,
+        user_agent: args.user_agent,
+        respect_robots: !args.ignore_robots,
+        use_sitemaps: !args.no_sitemap,
+        since: args.since,
+        resume: args.resume,
+        filter: UrlFilter::new(&args.include, &args.exclude)?,
+        profile: args.profile,
+        site_config,
+        wait: args.wait,
+        wait_timeout: Duration::from_secs(args.wait_timeout),
+        expand_hidden: !args.no_expand,
+        expand: args.expand,
+        scroll: args.scroll,
+        admonitions: args.admonitions,
+    })
+    .await?;
 
     Ok(())
 }
-
-fn sanitize_filename(filename: &str) -> String {
-    filename.replace(|c: char| !c.is_alphanumeric(), "-")
-}
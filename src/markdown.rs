@@ -0,0 +1,1778 @@
+use crate::images::{self, Image};
+use clap::ValueEnum;
+use ego_tree::{NodeId, NodeRef};
+use scraper::{ElementRef, Node};
+use serde::Deserialize;
+use std::cell::Cell;
+use std::collections::HashMap;
+use url::Url;
+
+/// Elements whose content is never part of the document text.
+const SKIPPED: &[&str] = &[
+    "script", "style", "noscript", "template", "head", "title", "meta", "link", "nav", "button",
+    "form", "select", "textarea", "iframe", "svg", "canvas", "object", "embed", "dialog", "source",
+];
+
+/// Elements that start a new block.
+const BLOCKS: &[&str] = &[
+    "html",
+    "body",
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "hr",
+    "li",
+    "main",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "ul",
+];
+
+/// Elements that always stay inside the surrounding paragraph, even when they
+/// (invalidly) contain blocks.
+const INLINE: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "br", "cite", "code", "data", "del", "dfn", "em", "font", "i",
+    "img", "input", "ins", "kbd", "label", "mark", "picture", "q", "s", "samp", "small", "span",
+    "strike", "strong", "sub", "sup", "time", "tt", "u", "var", "wbr",
+];
+
+/// Classes of copy-to-clipboard buttons and their labels.
+const COPY_CLASSES: &[&str] = &[
+    "copy",
+    "copybtn",
+    "copy-btn",
+    "btn-copy",
+    "copy-button",
+    "copy-code-button",
+    "code-copy",
+    "clipboard",
+    "clipboard-button",
+    "md-clipboard",
+    "hljs-copy-button",
+];
+
+/// Classes of line-number gutters in and next to code blocks (Pygments,
+/// Prism, highlight.js plugins and common themes).
+const LINE_NUMBER_CLASSES: &[&str] = &[
+    "linenos",
+    "linenodiv",
+    "lineno",
+    "line-number",
+    "line-numbers-rows",
+    "line-numbers-wrapper",
+    "hljs-ln-numbers",
+    "gutter",
+];
+
+/// How many wrappers above a `<pre>` may carry its language class, as in
+/// Sphinx's `div.highlight-python > div.highlight > pre`, which becomes seven
+/// levels deep once Pygments adds its line-number table.
+const CODE_WRAPPER_DEPTH: usize = 8;
+
+/// Language names that mean "no highlighting".
+const NO_LANGUAGE: &[&str] = &["none", "nohighlight", "default", "plain"];
+
+/// Elements kept when a table falls back to HTML; other elements are replaced
+/// by their content.
+const TABLE_HTML_ELEMENTS: &[&str] = &[
+    "table",
+    "caption",
+    "colgroup",
+    "col",
+    "thead",
+    "tbody",
+    "tfoot",
+    "tr",
+    "th",
+    "td",
+    "p",
+    "br",
+    "hr",
+    "ul",
+    "ol",
+    "li",
+    "dl",
+    "dt",
+    "dd",
+    "blockquote",
+    "pre",
+    "code",
+    "kbd",
+    "samp",
+    "strong",
+    "b",
+    "em",
+    "i",
+    "del",
+    "s",
+    "sup",
+    "sub",
+];
+
+/// Attributes kept on those elements.
+const TABLE_HTML_ATTRS: &[&str] = &["colspan", "rowspan", "scope", "align", "start", "title"];
+
+/// Elements without a closing tag.
+const VOID_ELEMENTS: &[&str] = &["br", "col", "hr", "img"];
+
+/// Table parts whose whitespace-only text is layout rather than content.
+const TABLE_STRUCTURE: &[&str] = &["table", "colgroup", "thead", "tbody", "tfoot", "tr"];
+
+/// Link texts that only mark a heading's permalink.
+const PERMALINK_TEXTS: &[&str] = &["", "#", "¶", "§", "🔗"];
+
+/// How notes, tips and warnings are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdmonitionStyle {
+    /// GitHub alerts: `> [!NOTE]`
+    #[default]
+    Gfm,
+    /// MkDocs admonitions: `!!! note`
+    Mkdocs,
+    /// Docusaurus admonitions: `:::note`
+    Docusaurus,
+}
+
+/// The kinds of admonition GitHub alerts distinguish, which every site's
+/// admonition types are folded into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Alert {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl Alert {
+    /// The type keyword for this kind in `style`.
+    fn keyword(self, style: AdmonitionStyle) -> &'static str {
+        match (style, self) {
+            (AdmonitionStyle::Gfm, Alert::Note) => "NOTE",
+            (AdmonitionStyle::Gfm, Alert::Tip) => "TIP",
+            (AdmonitionStyle::Gfm, Alert::Important) => "IMPORTANT",
+            (AdmonitionStyle::Gfm, Alert::Warning) => "WARNING",
+            (AdmonitionStyle::Gfm, Alert::Caution) => "CAUTION",
+            (_, Alert::Note) => "note",
+            (_, Alert::Tip) => "tip",
+            (AdmonitionStyle::Mkdocs, Alert::Important) => "important",
+            (_, Alert::Important) => "info",
+            (_, Alert::Warning) => "warning",
+            (_, Alert::Caution) => "danger",
+        }
+    }
+
+    /// The bold label that stands in for a GitHub alert's type where alerts
+    /// are not rendered, inside a quote.
+    fn label(self) -> &'static str {
+        match self {
+            Alert::Note => "Note",
+            Alert::Tip => "Tip",
+            Alert::Important => "Important",
+            Alert::Warning => "Warning",
+            Alert::Caution => "Caution",
+        }
+    }
+}
+
+/// Admonition types as sites name them, and the kind each becomes.
+const ALERT_TYPES: &[(&str, Alert)] = &[
+    ("note", Alert::Note),
+    ("info", Alert::Note),
+    ("abstract", Alert::Note),
+    ("summary", Alert::Note),
+    ("seealso", Alert::Note),
+    ("todo", Alert::Note),
+    ("example", Alert::Note),
+    ("question", Alert::Note),
+    ("quote", Alert::Note),
+    ("primary", Alert::Note),
+    ("secondary", Alert::Note),
+    ("tip", Alert::Tip),
+    ("hint", Alert::Tip),
+    ("success", Alert::Tip),
+    ("important", Alert::Important),
+    ("warning", Alert::Warning),
+    ("warn", Alert::Warning),
+    ("attention", Alert::Warning),
+    ("caution", Alert::Caution),
+    ("danger", Alert::Caution),
+    ("error", Alert::Caution),
+    ("failure", Alert::Caution),
+    ("bug", Alert::Caution),
+];
+
+/// How one kind of admonition widget is marked up: the class of its
+/// container, class prefixes naming its type, class prefixes of the
+/// children holding its title and its body, and the type assumed when none
+/// is named (`None` when the class alone does not make an admonition).
+struct AdmonitionMarkup {
+    class: &'static str,
+    type_prefixes: &'static [&'static str],
+    title: &'static [&'static str],
+    body: &'static [&'static str],
+    default_type: Option<&'static str>,
+}
+
+const ADMONITION_MARKUP: &[AdmonitionMarkup] = &[
+    // Docusaurus 3, whose children carry CSS module classes
+    AdmonitionMarkup {
+        class: "theme-admonition",
+        type_prefixes: &["theme-admonition-"],
+        title: &["admonitionHeading"],
+        body: &["admonitionContent"],
+        default_type: Some("note"),
+    },
+    // Sphinx, MkDocs and Docusaurus 2
+    AdmonitionMarkup {
+        class: "admonition",
+        type_prefixes: &["admonition-"],
+        title: &["admonition-title", "admonition-heading"],
+        body: &["admonition-content"],
+        default_type: Some("note"),
+    },
+    // Markdown rendered by GitHub
+    AdmonitionMarkup {
+        class: "markdown-alert",
+        type_prefixes: &["markdown-alert-"],
+        title: &["markdown-alert-title"],
+        body: &[],
+        default_type: Some("note"),
+    },
+    // VitePress custom containers, which also include collapsible details
+    AdmonitionMarkup {
+        class: "custom-block",
+        type_prefixes: &[],
+        title: &["custom-block-title"],
+        body: &[],
+        default_type: None,
+    },
+    // GitBook hints
+    AdmonitionMarkup {
+        class: "hint",
+        type_prefixes: &["hint-"],
+        title: &[],
+        body: &[],
+        default_type: Some("info"),
+    },
+    // Bootstrap alerts and the hints of legacy GitBook
+    AdmonitionMarkup {
+        class: "alert",
+        type_prefixes: &["alert--", "alert-"],
+        title: &["alert-heading"],
+        body: &["hints-container"],
+        default_type: Some("note"),
+    },
+];
+
+/// An admonition found in the page: its kind, the type name the site gave
+/// it, the element holding its title, and the element its body is read from.
+struct Admonition<'b> {
+    alert: Alert,
+    name: &'b str,
+    title: Option<ElementRef<'b>>,
+    body: ElementRef<'b>,
+}
+
+/// Converts a `scraper` element tree into CommonMark with GitHub extensions.
+///
+/// Every element has a fixed rule: block elements become paragraphs,
+/// headings, lists, quotes or code blocks; inline elements become emphasis,
+/// code spans, links and images; chrome such as scripts, navigation and
+/// buttons is skipped. Text is escaped rather than filtered, so nothing the
+/// page says is lost.
+pub struct Converter<'a> {
+    base_url: &'a str,
+    /// Images to show instead of an image element, keyed by the `<img>` or
+    /// enclosing `<picture>`, with `src` pointing at the downloaded copy;
+    /// `None` drops the image
+    images: &'a HashMap<NodeId, Option<Image>>,
+    admonitions: AdmonitionStyle,
+    /// Quotes and admonitions the element being converted sits in
+    quote_depth: Cell<usize>,
+}
+
+impl<'a> Converter<'a> {
+    pub fn new(
+        base_url: &'a str,
+        images: &'a HashMap<NodeId, Option<Image>>,
+        admonitions: AdmonitionStyle,
+    ) -> Self {
+        Converter {
+            base_url,
+            images,
+            admonitions,
+            quote_depth: Cell::new(0),
+        }
+    }
+
+    /// Converts `element` and everything below it.
+    pub fn convert(&self, element: &ElementRef) -> String {
+        let mut blocks = Vec::new();
+        let mut inline = Inline::default();
+        self.block_node(**element, &mut blocks, &mut inline);
+        flush_paragraph(&mut blocks, &mut inline);
+
+        let markdown = blocks.join("\n\n");
+        if markdown.is_empty() {
+            markdown
+        } else {
+            markdown + "\n"
+        }
+    }
+
+    /// Converts the children of `parent` into blocks.
+    fn blocks(&self, parent: NodeRef<Node>) -> Vec<String> {
+        let mut blocks = Vec::new();
+        let mut inline = Inline::default();
+        for child in parent.children() {
+            self.block_node(child, &mut blocks, &mut inline);
+        }
+        flush_paragraph(&mut blocks, &mut inline);
+        blocks
+    }
+
+    fn block_node(&self, node: NodeRef<Node>, blocks: &mut Vec<String>, inline: &mut Inline) {
+        let Some(element) = ElementRef::wrap(node) else {
+            self.inline_node(node, inline);
+            return;
+        };
+        if is_skipped(&element) {
+            return;
+        }
+        if is_block(&element) && !self.images.contains_key(&node.id()) {
+            flush_paragraph(blocks, inline);
+            blocks.extend(self.block(&element));
+        } else {
+            self.inline_node(node, inline);
+        }
+    }
+
+    fn block(&self, element: &ElementRef) -> Vec<String> {
+        if let Some(admonition) = admonition_parts(element) {
+            return self.admonition(&admonition).into_iter().collect();
+        }
+        if let Some(panels) = tab_panels(element) {
+            return self.tab_group(&panels);
+        }
+        let name = element.value().name();
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse::<usize>().unwrap_or(1);
+                let text = self.inline_text(element).replace("\\\n", " ");
+                if text.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![format!("{} {}", "#".repeat(level), text)]
+                }
+            }
+            "ul" | "ol" => self.list(element).into_iter().collect(),
+            "blockquote" => {
+                let inner = self.quoted(|| self.blocks(**element)).join("\n\n");
+                if inner.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![prefix_lines(&inner, ">")]
+                }
+            }
+            "pre" => self.code_block(element).into_iter().collect(),
+            "hr" => vec!["---".to_string()],
+            "table" => self.table(element),
+            "dt" | "summary" => {
+                let text = self.inline_text(element);
+                if text.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![format!("**{}**", text)]
+                }
+            }
+            _ => self.blocks(**element),
+        }
+    }
+
+    /// Renders an admonition in the configured style. A title other than the
+    /// type's name is kept: as a bold first line in a GitHub alert, which has
+    /// no title of its own, and as the title in the other styles. GitHub only
+    /// renders alerts outside quotes, so a nested one starts with its type as
+    /// a bold label instead.
+    fn admonition(&self, admonition: &Admonition) -> Option<String> {
+        let title = admonition
+            .title
+            .map(|title| self.inline_text(&title))
+            .unwrap_or_default();
+        let keyword = admonition.alert.keyword(self.admonitions);
+        let custom_title = (!title.is_empty()
+            && !title.eq_ignore_ascii_case(admonition.name)
+            && !title.eq_ignore_ascii_case(keyword))
+        .then_some(title);
+
+        let title_id = admonition.title.map(|title| title.id());
+        let blocks = self.quoted(|| {
+            let mut blocks = Vec::new();
+            let mut inline = Inline::default();
+            for child in admonition.body.children() {
+                if Some(child.id()) != title_id {
+                    self.block_node(child, &mut blocks, &mut inline);
+                }
+            }
+            flush_paragraph(&mut blocks, &mut inline);
+            blocks
+        });
+        if blocks.is_empty() && custom_title.is_none() {
+            return None;
+        }
+        let body = blocks.join("\n\n");
+
+        Some(match self.admonitions {
+            AdmonitionStyle::Gfm => {
+                let content: Vec<String> = custom_title
+                    .map(|title| format!("**{}**", title))
+                    .into_iter()
+                    .chain(blocks)
+                    .collect();
+                let text = if self.quote_depth.get() == 0 {
+                    format!("[!{}]\n{}", keyword, content.join("\n\n"))
+                } else {
+                    format!(
+                        "**{}**\n\n{}",
+                        admonition.alert.label(),
+                        content.join("\n\n")
+                    )
+                };
+                prefix_lines(&text, ">")
+            }
+            AdmonitionStyle::Mkdocs => {
+                let mut text = format!("!!! {}", keyword);
+                if let Some(title) = custom_title {
+                    text.push_str(&format!(" \"{}\"", title.replace('"', "'")));
+                }
+                if !body.is_empty() {
+                    text.push_str("\n\n");
+                    text.push_str(&indent_lines(&body));
+                }
+                text
+            }
+            AdmonitionStyle::Docusaurus => {
+                let fence = ":".repeat(longest_run(&body, ':').max(2) + 1);
+                let title = custom_title
+                    .map(|title| format!("[{}]", title))
+                    .unwrap_or_default();
+                if body.is_empty() {
+                    format!("{}{}{}\n{}", fence, keyword, title, fence)
+                } else {
+                    format!("{}{}{}\n\n{}\n\n{}", fence, keyword, title, body, fence)
+                }
+            }
+        })
+    }
+
+    /// Runs `convert` one quote deeper.
+    fn quoted<T>(&self, convert: impl FnOnce() -> T) -> T {
+        self.quote_depth.set(self.quote_depth.get() + 1);
+        let converted = convert();
+        self.quote_depth.set(self.quote_depth.get() - 1);
+        converted
+    }
+
+    /// Renders every panel of a tab widget in order, each under a bold label
+    /// with its tab's title. Panels the page hides are included, since only
+    /// the selected one is shown.
+    fn tab_group(&self, panels: &[(String, ElementRef)]) -> Vec<String> {
+        let mut blocks = Vec::new();
+        for (label, panel) in panels {
+            let content = self.blocks(**panel);
+            if content.is_empty() {
+                continue;
+            }
+            let mut title = Inline::default();
+            title.push_text(label);
+            let title = title.finish();
+            if !title.is_empty() {
+                blocks.push(format!("**{}**", title));
+            }
+            blocks.extend(content);
+        }
+        blocks
+    }
+
+    /// Renders a list, tight unless one of its items holds several blocks.
+    fn list(&self, element: &ElementRef) -> Option<String> {
+        let ordered = element.value().name() == "ol";
+        let mut number = element
+            .value()
+            .attr("start")
+            .and_then(|start| start.trim().parse::<usize>().ok())
+            .unwrap_or(1);
+
+        let mut items = Vec::new();
+        for item in element.children().filter_map(ElementRef::wrap) {
+            if item.value().name() != "li" || is_skipped(&item) {
+                continue;
+            }
+            let marker = if ordered {
+                format!("{}. ", number)
+            } else {
+                "- ".to_string()
+            };
+            number += 1;
+
+            let mut body = String::new();
+            for block in self.blocks(*item) {
+                if !body.is_empty() {
+                    // Nested lists stay attached to the item's first line
+                    // when markdown lets them interrupt a paragraph
+                    body.push_str(if interrupts_paragraph(&block) {
+                        "\n"
+                    } else {
+                        "\n\n"
+                    });
+                }
+                body.push_str(&block);
+            }
+            items.push(list_item(&marker, &body));
+        }
+
+        if items.is_empty() {
+            return None;
+        }
+        let loose = items.iter().any(|item| item.contains("\n\n"));
+        Some(items.join(if loose { "\n\n" } else { "\n" }))
+    }
+
+    /// Renders `<pre>` as a fenced code block tagged with its language. The
+    /// text is kept exactly, without line numbers or copy buttons, and the
+    /// fence is longer than any backtick run inside it.
+    fn code_block(&self, pre: &ElementRef) -> Option<String> {
+        let mut code = String::new();
+        code_text(**pre, &mut code, &mut false);
+        let code = code.strip_suffix('\n').unwrap_or(&code);
+        if code.trim().is_empty() {
+            return None;
+        }
+        let fence = "`".repeat(longest_run(code, '`').max(2) + 1);
+        let language = code_language(pre).unwrap_or_default();
+        Some(format!("{}{}\n{}\n{}", fence, language, code, fence))
+    }
+
+    /// Renders a table as a GitHub pipe table, or as sanitized HTML when it
+    /// has merged cells, nested tables or block content that a pipe table
+    /// cannot hold.
+    fn table(&self, table: &ElementRef) -> Vec<String> {
+        if let Some(pre) = gutter_table_code(table) {
+            return self.code_block(&pre).into_iter().collect();
+        }
+        let rows = table_rows(table);
+        if rows.is_empty() {
+            return Vec::new();
+        }
+        match self.pipe_table(table, &rows) {
+            Some(blocks) => blocks,
+            None => vec![self.table_html(table)],
+        }
+    }
+
+    fn pipe_table(&self, table: &ElementRef, rows: &[(ElementRef, bool)]) -> Option<Vec<String>> {
+        let nested = table
+            .descendants()
+            .skip(1)
+            .filter_map(ElementRef::wrap)
+            .any(|element| element.value().name() == "table");
+        let head_rows = rows.iter().filter(|(_, in_head)| *in_head).count();
+        if nested || head_rows > 1 {
+            return None;
+        }
+
+        let mut cells: Vec<Vec<String>> = Vec::new();
+        for (row, _) in rows {
+            let mut row_cells = Vec::new();
+            for cell in table_cells(row) {
+                let spans = ["colspan", "rowspan"].iter().any(|attr| {
+                    cell.value()
+                        .attr(attr)
+                        .and_then(|span| span.trim().parse::<usize>().ok())
+                        .is_some_and(|span| span > 1)
+                });
+                if spans {
+                    return None;
+                }
+                row_cells.push(self.table_cell(&cell)?);
+            }
+            cells.push(row_cells);
+        }
+
+        // The header is the `<thead>` row, or a first row made of `<th>` only
+        let (first_row, _) = &rows[0];
+        let has_header = head_rows == 1
+            || table_cells(first_row)
+                .iter()
+                .all(|cell| cell.value().name() == "th");
+        let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return Some(Vec::new());
+        }
+        let header = if has_header {
+            cells.remove(0)
+        } else {
+            Vec::new()
+        };
+        let alignments: Vec<&str> = (0..columns)
+            .map(|i| {
+                table_cells(first_row)
+                    .get(i)
+                    .map_or("---", column_alignment)
+            })
+            .collect();
+
+        let mut lines = vec![table_line(&header, columns)];
+        lines.push(format!("| {} |", alignments.join(" | ")));
+        for row in &cells {
+            lines.push(table_line(row, columns));
+        }
+
+        let mut blocks = Vec::new();
+        if let Some(caption) = table
+            .children()
+            .filter_map(ElementRef::wrap)
+            .find(|child| child.value().name() == "caption")
+        {
+            let caption = self.inline_text(&caption);
+            if !caption.is_empty() {
+                blocks.push(caption);
+            }
+        }
+        blocks.push(lines.join("\n"));
+        Some(blocks)
+    }
+
+    /// The inline markdown of a table cell, or `None` when the cell holds
+    /// more than a single paragraph.
+    fn table_cell(&self, cell: &ElementRef) -> Option<String> {
+        let has_blocks = cell
+            .descendants()
+            .skip(1)
+            .filter_map(ElementRef::wrap)
+            .any(|element| {
+                let name = element.value().name();
+                is_block(&element) && !matches!(name, "p" | "div") && !is_skipped(&element)
+            });
+        if has_blocks {
+            return None;
+        }
+        let blocks = self.blocks(**cell);
+        let text = match blocks.as_slice() {
+            [] => String::new(),
+            [text] => text.replace("\\\n", "<br>"),
+            _ => return None,
+        };
+        if text.contains('\n') {
+            return None;
+        }
+        Some(text.replace('|', "\\|"))
+    }
+
+    /// Serializes a table as HTML, keeping its structure, spans and inline
+    /// formatting but dropping classes, styles, scripts and page chrome.
+    fn table_html(&self, table: &ElementRef) -> String {
+        let mut html = String::new();
+        self.sanitized_html(**table, false, &mut html);
+        html.trim_end().to_string()
+    }
+
+    fn sanitized_html(&self, node: NodeRef<Node>, in_pre: bool, out: &mut String) {
+        if let Some(image) = self.images.get(&node.id()) {
+            if let Some(image) = image {
+                out.push_str(&image_html(image));
+            }
+            return;
+        }
+        let element = match node.value() {
+            Node::Text(text) => {
+                let parent = node.parent().and_then(ElementRef::wrap);
+                let is_layout =
+                    parent.is_some_and(|parent| TABLE_STRUCTURE.contains(&parent.value().name()));
+                if in_pre {
+                    out.push_str(&preformatted_html(text));
+                } else if !(is_layout && text.trim().is_empty()) {
+                    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if text.starts_with(char::is_whitespace) {
+                        out.push(' ');
+                    }
+                    out.push_str(&escape_html(&collapsed));
+                    if text.ends_with(char::is_whitespace) && !collapsed.is_empty() {
+                        out.push(' ');
+                    }
+                }
+                return;
+            }
+            Node::Element(_) => match ElementRef::wrap(node) {
+                Some(element) if !is_skipped(&element) => element,
+                _ => return,
+            },
+            _ => return,
+        };
+
+        let name = element.value().name();
+        let in_pre = in_pre || name == "pre";
+        let attrs: Vec<(&str, String)> = match name {
+            "img" => {
+                if let Some(image) = images::resolve_image(&element, self.base_url) {
+                    out.push_str(&image_html(&image));
+                }
+                return;
+            }
+            "a" => {
+                let href = element
+                    .value()
+                    .attr("href")
+                    .and_then(|href| absolute_href(href.trim(), self.base_url));
+                match href {
+                    Some(href) => vec![("href", href)],
+                    None => {
+                        for child in element.children() {
+                            self.sanitized_html(child, in_pre, out);
+                        }
+                        return;
+                    }
+                }
+            }
+            _ if TABLE_HTML_ELEMENTS.contains(&name) => element
+                .value()
+                .attrs()
+                .filter(|(attr, _)| TABLE_HTML_ATTRS.contains(attr))
+                .map(|(attr, value)| (attr, value.to_string()))
+                .collect(),
+            _ => {
+                for child in element.children() {
+                    self.sanitized_html(child, in_pre, out);
+                }
+                return;
+            }
+        };
+
+        out.push('<');
+        out.push_str(name);
+        for (attr, value) in attrs {
+            out.push_str(&format!(" {}=\"{}\"", attr, escape_html(&value)));
+        }
+        out.push('>');
+        if VOID_ELEMENTS.contains(&name) {
+            return;
+        }
+        if matches!(name, "table" | "thead" | "tbody" | "tfoot") {
+            out.push('\n');
+        }
+        for child in element.children() {
+            self.sanitized_html(child, in_pre, out);
+        }
+        out.push_str(&format!("</{}>", name));
+        if matches!(
+            name,
+            "tr" | "caption" | "colgroup" | "thead" | "tbody" | "tfoot"
+        ) {
+            out.push('\n');
+        }
+    }
+
+    /// The inline markdown of `element`'s children.
+    fn inline_text(&self, element: &ElementRef) -> String {
+        let mut inline = Inline::default();
+        self.inline_children(element, &mut inline);
+        inline.finish()
+    }
+
+    fn inline_children(&self, element: &ElementRef, out: &mut Inline) {
+        for child in element.children() {
+            self.inline_node(child, out);
+        }
+    }
+
+    fn inline_node(&self, node: NodeRef<Node>, out: &mut Inline) {
+        if let Some(image) = self.images.get(&node.id()) {
+            if let Some(image) = image {
+                out.push_raw(&image.to_markdown(&image.src));
+            }
+            return;
+        }
+        match node.value() {
+            Node::Text(text) => out.push_text(text),
+            Node::Element(_) => {
+                if let Some(element) = ElementRef::wrap(node) {
+                    self.inline_element(&element, out);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn inline_element(&self, element: &ElementRef, out: &mut Inline) {
+        if is_skipped(element) {
+            return;
+        }
+        let name = element.value().name();
+        match name {
+            "br" => out.hard_break(),
+            "strong" | "b" => self.wrap(element, "**", "**", out),
+            "em" | "i" | "cite" | "dfn" | "var" => self.wrap(element, "*", "*", out),
+            "del" | "s" | "strike" => self.wrap(element, "~~", "~~", out),
+            "sup" => self.wrap(element, "<sup>", "</sup>", out),
+            "sub" => self.wrap(element, "<sub>", "</sub>", out),
+            "q" => self.wrap(element, "\"", "\"", out),
+            "code" | "kbd" | "samp" | "tt" => {
+                let code: String = element.text().collect();
+                out.push_code(&code);
+            }
+            "a" => self.link(element, out),
+            "img" => {
+                if let Some(image) = images::resolve_image(element, self.base_url) {
+                    out.push_raw(&image.to_markdown(&image.src));
+                }
+            }
+            "input" => {
+                if element.value().attr("type") == Some("checkbox") {
+                    let checked = element.value().attr("checked").is_some();
+                    out.push_raw(if checked { "[x] " } else { "[ ] " });
+                }
+            }
+            _ if BLOCKS.contains(&name) => {
+                // A block inside an inline element only separates words
+                out.space();
+                self.inline_children(element, out);
+                out.space();
+            }
+            _ => self.inline_children(element, out),
+        }
+    }
+
+    /// Wraps the inline content of `element` in `open` and `close`, keeping
+    /// surrounding whitespace outside the markers so they stay valid.
+    fn wrap(&self, element: &ElementRef, open: &str, close: &str, out: &mut Inline) {
+        let mut inner = Inline::default();
+        self.inline_children(element, &mut inner);
+        out.push_wrapped(inner, open, close);
+    }
+
+    fn link(&self, element: &ElementRef, out: &mut Inline) {
+        let mut inner = Inline::default();
+        self.inline_children(element, &mut inner);
+
+        let href = element.value().attr("href").map(str::trim);
+        let text = inner.out.trim();
+        if href.is_some_and(|href| href.starts_with('#')) && PERMALINK_TEXTS.contains(&text) {
+            return;
+        }
+
+        let Some(url) = href.and_then(|href| absolute_href(href, self.base_url)) else {
+            // Links without a usable target keep their text
+            out.push_wrapped(inner, "", "");
+            return;
+        };
+        let title = element
+            .value()
+            .attr("title")
+            .map(str::trim)
+            .filter(|title| !title.is_empty())
+            .map(|title| format!(" \"{}\"", title.replace('"', "\\\"")))
+            .unwrap_or_default();
+        let close = format!("]({}{})", url, title);
+        out.push_wrapped(inner, "[", &close);
+    }
+}
+
+fn is_skipped(element: &ElementRef) -> bool {
+    let value = element.value();
+    SKIPPED.contains(&value.name())
+        || value.attr("hidden").is_some()
+        || value.attr("aria-hidden") == Some("true")
+        || value.attr("role") == Some("navigation")
+        || value.name() == "clipboard-copy"
+        || has_class(element, COPY_CLASSES)
+}
+
+fn has_class(element: &ElementRef, classes: &[&str]) -> bool {
+    element
+        .value()
+        .classes()
+        .any(|class| classes.contains(&class))
+}
+
+/// Appends the text of a code block below `node`, skipping line numbers and
+/// copy buttons. Lines laid out as `<div>`s, table rows or `<br>`s get their
+/// line breaks; `block_ended` records a break added that way, so that a
+/// newline already present in the source is not doubled.
+fn code_text(node: NodeRef<Node>, out: &mut String, block_ended: &mut bool) {
+    for child in node.children() {
+        match child.value() {
+            Node::Text(text) => {
+                let text = if *block_ended {
+                    text.strip_prefix('\n').unwrap_or(text)
+                } else {
+                    text
+                };
+                if !text.is_empty() {
+                    out.push_str(text);
+                    *block_ended = false;
+                }
+            }
+            Node::Element(_) => {
+                let Some(element) = ElementRef::wrap(child) else {
+                    continue;
+                };
+                if is_skipped(&element) || has_class(&element, LINE_NUMBER_CLASSES) {
+                    continue;
+                }
+                match element.value().name() {
+                    "br" => {
+                        out.push('\n');
+                        *block_ended = false;
+                    }
+                    "div" | "p" | "tr" => {
+                        code_text(child, out, block_ended);
+                        if !out.is_empty() && !out.ends_with('\n') {
+                            out.push('\n');
+                            *block_ended = true;
+                        }
+                    }
+                    _ => code_text(child, out, block_ended),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The language of a code block, from the class names and `data-*`
+/// attributes highlighters leave on the `<pre>`, its `<code>` and the
+/// wrappers around them.
+fn code_language(pre: &ElementRef) -> Option<String> {
+    let code = pre
+        .children()
+        .filter_map(ElementRef::wrap)
+        .find(|child| child.value().name() == "code");
+    let own = code.iter().chain(std::iter::once(pre));
+    let wrappers = pre
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .take(CODE_WRAPPER_DEPTH);
+
+    own.clone()
+        .find_map(|element| {
+            let value = element.value();
+            let by_attr = value.attr("data-language").or(value.attr("data-lang"));
+            by_attr.or_else(|| element_language(element, true))
+        })
+        .or_else(|| {
+            wrappers
+                .into_iter()
+                .find_map(|element| element_language(&element, false))
+        })
+        .map(str::to_lowercase)
+        .filter(|language| {
+            !NO_LANGUAGE.contains(&language.as_str())
+                && language
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '+' | '#' | '.'))
+        })
+}
+
+/// The language named by `element`'s classes. Short `lang-` prefixes and
+/// bare highlight.js names are only trusted on the code itself, since pages
+/// also use them for the natural language of the text.
+fn element_language<'b>(element: &ElementRef<'b>, is_code: bool) -> Option<&'b str> {
+    let classes: Vec<&str> = element.value().classes().collect();
+    classes
+        .iter()
+        .find_map(|class| {
+            class
+                .strip_prefix("language-")
+                .or_else(|| class.strip_prefix("highlight-source-"))
+                .or_else(|| class.strip_prefix("highlight-"))
+                .or_else(|| class.strip_prefix("lang-").filter(|_| is_code))
+                .filter(|language| !language.is_empty())
+        })
+        .or_else(|| {
+            // highlight.js without a prefix: `<code class="hljs rust">`
+            (is_code && classes.contains(&"hljs"))
+                .then(|| {
+                    classes
+                        .iter()
+                        .find(|class| !class.starts_with("hljs"))
+                        .copied()
+                })
+                .flatten()
+        })
+}
+
+/// The `<pre>` of a table that only lays out a code block next to its
+/// line-number gutter, as Pygments and some themes render them.
+fn gutter_table_code<'b>(table: &ElementRef<'b>) -> Option<ElementRef<'b>> {
+    let mut elements = table.descendants().filter_map(ElementRef::wrap);
+    let has_gutter = elements.clone().any(|element| {
+        matches!(element.value().name(), "td" | "th") && has_class(&element, LINE_NUMBER_CLASSES)
+    });
+    if !has_gutter {
+        return None;
+    }
+    elements.find(|element| {
+        element.value().name() == "pre"
+            && !element
+                .ancestors()
+                .filter_map(ElementRef::wrap)
+                .any(|ancestor| has_class(&ancestor, LINE_NUMBER_CLASSES))
+    })
+}
+
+/// The length of the longest run of `c` in `text`.
+fn longest_run(text: &str, c: char) -> usize {
+    let mut longest = 0;
+    let mut run = 0;
+    for next in text.chars() {
+        run = if next == c { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    longest
+}
+
+/// The parts of `element` when it is an admonition: a container of a known
+/// widget whose type is named by a prefixed class, a bare type class (as in
+/// `admonition warning`) or the widget's default, or a MkDocs Material
+/// collapsible `<details>` with a type class.
+fn admonition_parts<'b>(element: &ElementRef<'b>) -> Option<Admonition<'b>> {
+    let value = element.value();
+    let alert = |name: &str| {
+        ALERT_TYPES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|(_, alert)| *alert)
+    };
+    let named_type = || value.classes().find(|class| alert(class).is_some());
+
+    if value.name() == "details" {
+        let name = named_type()?;
+        return Some(Admonition {
+            alert: alert(name)?,
+            name,
+            title: element
+                .children()
+                .filter_map(ElementRef::wrap)
+                .find(|child| child.value().name() == "summary"),
+            body: *element,
+        });
+    }
+
+    let markup = ADMONITION_MARKUP
+        .iter()
+        .find(|markup| has_class(element, &[markup.class]))?;
+    let name = value
+        .classes()
+        .find_map(|class| {
+            markup
+                .type_prefixes
+                .iter()
+                .find_map(|prefix| class.strip_prefix(prefix))
+                .filter(|name| alert(name).is_some())
+        })
+        .or_else(named_type)
+        .or(markup.default_type)?;
+    let title = child_with_class(element, markup.title);
+    let body = child_with_class(element, markup.body).unwrap_or(*element);
+    Some(Admonition {
+        alert: alert(name)?,
+        name,
+        title,
+        body,
+    })
+}
+
+/// The first child of `element` with a class starting with one of `prefixes`.
+fn child_with_class<'b>(element: &ElementRef<'b>, prefixes: &[&str]) -> Option<ElementRef<'b>> {
+    element
+        .children()
+        .filter_map(ElementRef::wrap)
+        .find(|child| {
+            child
+                .value()
+                .classes()
+                .any(|class| prefixes.iter().any(|prefix| class.starts_with(prefix)))
+        })
+}
+
+/// The panels of the tab widget `element`, each with its tab's title, in
+/// order, or `None` when `element` is not a tab widget. Recognized are ARIA
+/// tab lists (Docusaurus, sphinx-tabs, and the panels the browser backend
+/// labels with `data-tab-label`), MkDocs Material and sphinx-design tabbed
+/// sets, and VitePress code groups.
+fn tab_panels<'b>(element: &ElementRef<'b>) -> Option<Vec<(String, ElementRef<'b>)>> {
+    let children: Vec<ElementRef<'b>> = element.children().filter_map(ElementRef::wrap).collect();
+    let child_elements = |parent: &ElementRef<'b>| -> Vec<ElementRef<'b>> {
+        parent.children().filter_map(ElementRef::wrap).collect()
+    };
+    let is_label = |child: &ElementRef| child.value().name() == "label";
+
+    let (labels, panels): (Vec<String>, Vec<ElementRef<'b>>) =
+        if has_class(element, &["tabbed-set", "sd-tab-set"]) {
+            // Labels sit next to their panels, or all together in
+            // `.tabbed-labels` with the panels as `.tabbed-block`s
+            let labels = children
+                .iter()
+                .flat_map(|child| {
+                    if has_class(child, &["tabbed-labels"]) {
+                        child_elements(child)
+                    } else {
+                        vec![*child]
+                    }
+                })
+                .filter(is_label)
+                .map(|label| tab_title(&label))
+                .collect();
+            let panels = children
+                .iter()
+                .filter(|child| has_class(child, &["tabbed-content", "sd-tab-content"]))
+                .flat_map(|content| {
+                    let blocks: Vec<_> = child_elements(content)
+                        .into_iter()
+                        .filter(|block| has_class(block, &["tabbed-block"]))
+                        .collect();
+                    if blocks.is_empty() {
+                        vec![*content]
+                    } else {
+                        blocks
+                    }
+                })
+                .collect();
+            (labels, panels)
+        } else if has_class(element, &["vp-code-group"]) {
+            let labels = children
+                .iter()
+                .filter(|child| has_class(child, &["tabs"]))
+                .flat_map(child_elements)
+                .filter(is_label)
+                .map(|label| match label.value().attr("data-title") {
+                    Some(title) => title.trim().to_string(),
+                    None => tab_title(&label),
+                })
+                .collect();
+            let panels = children
+                .iter()
+                .filter(|child| has_class(child, &["blocks"]))
+                .flat_map(child_elements)
+                .collect();
+            (labels, panels)
+        } else {
+            let list = children
+                .iter()
+                .find(|child| child.value().attr("role") == Some("tablist"))?;
+            let tabs: Vec<ElementRef> = list
+                .descendants()
+                .filter_map(ElementRef::wrap)
+                .filter(|tab| tab.value().attr("role") == Some("tab"))
+                .collect();
+            let mut panels = Vec::new();
+            for child in &children {
+                top_tab_panels(child, &mut panels);
+            }
+            let labels = panels
+                .iter()
+                .enumerate()
+                .map(|(i, panel)| {
+                    if let Some(label) = panel.value().attr("data-tab-label") {
+                        return label.trim().to_string();
+                    }
+                    let labelled_by = panel.value().attr("aria-labelledby");
+                    tabs.iter()
+                        .find(|tab| labelled_by.is_some() && tab.value().id() == labelled_by)
+                        .or_else(|| tabs.get(i))
+                        .map(tab_title)
+                        .unwrap_or_default()
+                })
+                .collect();
+            (labels, panels)
+        };
+
+    if panels.is_empty() || labels.len() != panels.len() {
+        return None;
+    }
+    Some(labels.into_iter().zip(panels).collect())
+}
+
+/// Collects the tab panels at or below `element` that are not inside
+/// another panel, leaving nested tab widgets to their own conversion.
+fn top_tab_panels<'b>(element: &ElementRef<'b>, panels: &mut Vec<ElementRef<'b>>) {
+    match element.value().attr("role") {
+        Some("tabpanel") => panels.push(*element),
+        Some("tablist") => {}
+        _ => {
+            for child in element.children().filter_map(ElementRef::wrap) {
+                top_tab_panels(&child, panels);
+            }
+        }
+    }
+}
+
+/// The text of a tab, with its whitespace collapsed.
+fn tab_title(tab: &ElementRef) -> String {
+    tab.text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether `element` starts a block: a known block element, or an unknown
+/// (custom) element that contains blocks.
+fn is_block(element: &ElementRef) -> bool {
+    let name = element.value().name();
+    if BLOCKS.contains(&name) {
+        return true;
+    }
+    !INLINE.contains(&name)
+        && element
+            .descendants()
+            .skip(1)
+            .filter_map(ElementRef::wrap)
+            .any(|child| BLOCKS.contains(&child.value().name()))
+}
+
+/// Whether `block` is a list that may start right after a paragraph line:
+/// a bullet list, or an ordered list starting at 1.
+fn interrupts_paragraph(block: &str) -> bool {
+    block.starts_with("- ") || block.starts_with("1. ")
+}
+
+/// The rows of a table in document order, each flagged when it sits in the
+/// `<thead>`.
+fn table_rows<'b>(table: &ElementRef<'b>) -> Vec<(ElementRef<'b>, bool)> {
+    let mut rows = Vec::new();
+    for child in table.children().filter_map(ElementRef::wrap) {
+        match child.value().name() {
+            "tr" => rows.push((child, false)),
+            section @ ("thead" | "tbody" | "tfoot") => rows.extend(
+                child
+                    .children()
+                    .filter_map(ElementRef::wrap)
+                    .filter(|row| row.value().name() == "tr")
+                    .map(|row| (row, section == "thead")),
+            ),
+            _ => {}
+        }
+    }
+    rows.retain(|(row, _)| !is_skipped(row));
+    rows
+}
+
+fn table_cells<'b>(row: &ElementRef<'b>) -> Vec<ElementRef<'b>> {
+    row.children()
+        .filter_map(ElementRef::wrap)
+        .filter(|cell| matches!(cell.value().name(), "th" | "td") && !is_skipped(cell))
+        .collect()
+}
+
+/// The delimiter-row cell for the alignment of a header cell, taken from its
+/// `align` attribute or `text-align` style.
+fn column_alignment(cell: &ElementRef) -> &'static str {
+    let style = cell.value().attr("style").unwrap_or("").to_lowercase();
+    let align = cell
+        .value()
+        .attr("align")
+        .map(str::to_lowercase)
+        .or_else(|| {
+            style
+                .split(';')
+                .filter_map(|rule| rule.split_once(':'))
+                .find(|(property, _)| property.trim() == "text-align")
+                .map(|(_, value)| value.trim().to_string())
+        });
+    match align.as_deref() {
+        Some("left" | "start") => ":---",
+        Some("center") => ":---:",
+        Some("right" | "end") => "---:",
+        _ => "---",
+    }
+}
+
+/// One pipe-table line, padded with empty cells to `columns`.
+fn table_line(cells: &[String], columns: usize) -> String {
+    let cells: Vec<&str> = (0..columns)
+        .map(|i| cells.get(i).map_or("", String::as_str))
+        .collect();
+    format!("| {} |", cells.join(" | "))
+}
+
+fn image_html(image: &Image) -> String {
+    let title = image
+        .title
+        .as_ref()
+        .map(|title| format!(" title=\"{}\"", escape_html(title)))
+        .unwrap_or_default();
+    format!(
+        "<img src=\"{}\" alt=\"{}\"{}>",
+        escape_html(&image.src),
+        escape_html(&image.alt),
+        title
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Escapes preformatted text for an HTML block. A blank line would end the
+/// block, so the line break before one is written as a character reference.
+fn preformatted_html(text: &str) -> String {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let mut html = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            let blank = line.trim().is_empty() && i < lines.len() - 1;
+            html.push_str(if blank { "&#10;" } else { "\n" });
+        }
+        html.push_str(&escape_html(line));
+    }
+    html
+}
+
+/// Prefixes the first line of `body` with `marker` and indents the rest to
+/// line up under it.
+fn list_item(marker: &str, body: &str) -> String {
+    if body.is_empty() {
+        return marker.trim_end().to_string();
+    }
+    let indent = " ".repeat(marker.len());
+    body.lines()
+        .enumerate()
+        .map(|(i, line)| match (i, line.is_empty()) {
+            (0, _) => format!("{}{}", marker, line),
+            (_, true) => String::new(),
+            _ => format!("{}{}", indent, line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Indents every non-empty line of `text` by four spaces.
+fn indent_lines(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("    {}", line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn prefix_lines(text: &str, prefix: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                prefix.to_string()
+            } else {
+                format!("{} {}", prefix, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn flush_paragraph(blocks: &mut Vec<String>, inline: &mut Inline) {
+    let text = std::mem::take(inline).finish();
+    if !text.is_empty() {
+        blocks.push(
+            text.lines()
+                .map(escape_line_start)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+    }
+}
+
+/// Escapes text at the start of a paragraph line that markdown would
+/// otherwise read as a heading, quote, list item or rule.
+fn escape_line_start(line: &str) -> String {
+    let hashes = line.chars().take_while(|&c| c == '#').count();
+    let after_hashes = &line[hashes..];
+    let is_marker = (1..=6).contains(&hashes)
+        && (after_hashes.is_empty() || after_hashes.starts_with(' '))
+        || line.starts_with('>')
+        || ((line.starts_with('-') || line.starts_with('+'))
+            && (line.len() == 1 || line[1..].starts_with(' ')))
+        || (!line.is_empty() && line.trim_end().chars().all(|c| c == '=' || c == '-'));
+    if is_marker {
+        return format!("\\{}", line);
+    }
+
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    let rest = &line[digits..];
+    if (1..=9).contains(&digits)
+        && (rest.starts_with(". ") || rest.starts_with(") ") || rest == "." || rest == ")")
+    {
+        return format!("{}\\{}", &line[..digits], rest);
+    }
+    line.to_string()
+}
+
+/// Resolves a link target against the page URL, keeping its fragment.
+/// Parentheses are percent-encoded so the target never ends the link early.
+fn absolute_href(href: &str, base_url: &str) -> Option<String> {
+    if href.is_empty() || href.starts_with("javascript:") {
+        return None;
+    }
+    let url = match Url::parse(href) {
+        Ok(url) => url,
+        Err(_) => Url::parse(base_url).ok()?.join(href).ok()?,
+    };
+    Some(url.to_string().replace('(', "%28").replace(')', "%29"))
+}
+
+/// Applies `rewrite` to the prose of `markdown`, leaving fenced code blocks
+/// and inline code spans as they are.
+pub fn rewrite_prose(markdown: &str, rewrite: impl Fn(&str) -> String) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let mut prose = String::new();
+    let mut open: Option<(char, usize)> = None;
+    for line in markdown.split_inclusive('\n') {
+        match (open, code_fence(line)) {
+            (None, Some((c, len, _))) => {
+                out.push_str(&rewrite_outside_spans(&prose, &rewrite));
+                prose.clear();
+                open = Some((c, len));
+                out.push_str(line);
+            }
+            (None, None) => prose.push_str(line),
+            (Some((c, len)), Some((close, close_len, ""))) if close == c && close_len >= len => {
+                open = None;
+                out.push_str(line);
+            }
+            (Some(_), _) => out.push_str(line),
+        }
+    }
+    out.push_str(&rewrite_outside_spans(&prose, &rewrite));
+    out
+}
+
+/// The character, length and info string of the code fence `line` starts,
+/// past any blockquote markers and indentation.
+fn code_fence(line: &str) -> Option<(char, usize, &str)> {
+    let line = line.trim_start_matches(|c: char| c == '>' || c.is_whitespace());
+    let c = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = line.len() - line.trim_start_matches(c).len();
+    (len >= 3).then(|| (c, len, line[len..].trim()))
+}
+
+/// Applies `rewrite` to `text` between its inline code spans. A span opened by
+/// a run of backticks ends at the next run of the same length; a run with no
+/// such match is plain text.
+fn rewrite_outside_spans(text: &str, rewrite: &impl Fn(&str) -> String) -> String {
+    let bytes = text.as_bytes();
+    let run_at = |i: usize| bytes[i..].iter().take_while(|&&b| b == b'`').count();
+    let mut out = String::with_capacity(text.len());
+    let mut prose_start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'`' => {
+                let run = run_at(i);
+                let mut j = i + run;
+                let mut close = None;
+                while j < bytes.len() {
+                    if bytes[j] == b'`' {
+                        let next = run_at(j);
+                        if next == run {
+                            close = Some(j + run);
+                            break;
+                        }
+                        j += next;
+                    } else {
+                        j += 1;
+                    }
+                }
+                match close {
+                    Some(end) => {
+                        out.push_str(&rewrite(&text[prose_start..i]));
+                        out.push_str(&text[i..end]);
+                        prose_start = end;
+                        i = end;
+                    }
+                    None => i += run,
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    if prose_start < text.len() {
+        out.push_str(&rewrite(&text[prose_start..]));
+    }
+    out
+}
+
+/// Inline markdown being assembled, with HTML whitespace collapsing: runs of
+/// whitespace become one space, and spaces at the edges are dropped.
+#[derive(Default)]
+struct Inline {
+    out: String,
+    /// Whitespace seen before any content
+    leading_space: bool,
+    /// Whitespace seen since the last content
+    pending_space: bool,
+}
+
+impl Inline {
+    fn space(&mut self) {
+        if self.out.is_empty() {
+            self.leading_space = true;
+        } else {
+            self.pending_space = true;
+        }
+    }
+
+    fn flush_space(&mut self) {
+        if self.pending_space && !self.out.is_empty() && !self.out.ends_with([' ', '\n']) {
+            self.out.push(' ');
+        }
+        self.pending_space = false;
+    }
+
+    fn push_raw(&mut self, markdown: &str) {
+        if markdown.is_empty() {
+            return;
+        }
+        self.flush_space();
+        self.out.push_str(markdown);
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let chars: Vec<char> = text.chars().collect();
+        for (i, &c) in chars.iter().enumerate() {
+            if c.is_whitespace() {
+                self.space();
+                continue;
+            }
+            self.flush_space();
+            match c {
+                '\\' | '*' | '`' | '[' | ']' | '<' => self.out.push('\\'),
+                '_' => {
+                    // Underscores inside words never start emphasis in GFM
+                    let before = self.out.chars().last().is_some_and(char::is_alphanumeric);
+                    let after = chars.get(i + 1).is_some_and(|c| c.is_alphanumeric());
+                    if !(before && after) {
+                        self.out.push('\\');
+                    }
+                }
+                _ => {}
+            }
+            self.out.push(c);
+        }
+    }
+
+    /// Adds a code span, with enough backticks around it to contain any
+    /// backticks in `code`.
+    fn push_code(&mut self, code: &str) {
+        let code = code.split_whitespace().collect::<Vec<_>>().join(" ");
+        if code.is_empty() {
+            return;
+        }
+        let fence = "`".repeat(longest_run(&code, '`') + 1);
+        let pad = if code.starts_with('`') || code.ends_with('`') {
+            " "
+        } else {
+            ""
+        };
+        self.push_raw(&format!("{}{}{}{}{}", fence, pad, code, pad, fence));
+    }
+
+    fn hard_break(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push_str("\\\n");
+        }
+        self.pending_space = false;
+    }
+
+    /// Adds `inner` between `open` and `close`, moving its outer whitespace
+    /// outside the markers.
+    fn push_wrapped(&mut self, inner: Inline, open: &str, close: &str) {
+        if inner.leading_space {
+            self.space();
+        }
+        let trailing_space = inner.pending_space;
+        let text = inner.finish();
+        if !text.is_empty() {
+            self.push_raw(&format!("{}{}{}", open, text, close));
+        }
+        if trailing_space {
+            self.space();
+        }
+    }
+
+    /// Returns the markdown without surrounding whitespace or trailing hard breaks.
+    fn finish(self) -> String {
+        let mut out = self.out.as_str();
+        loop {
+            let trimmed = out.trim_end_matches(' ');
+            match trimmed.strip_suffix("\\\n") {
+                Some(stripped) => out = stripped,
+                None => {
+                    out = trimmed;
+                    break;
+                }
+            }
+        }
+        out.trim().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scraper::Html;
+
+    fn convert(html: &str) -> String {
+        let document = Html::parse_fragment(html);
+        Converter::new(
+            "https://example.com/docs/page",
+            &HashMap::new(),
+            AdmonitionStyle::Gfm,
+        )
+        .convert(&document.root_element())
+    }
+
+    #[test]
+    fn test_golden_text() {
+        assert_eq!(
+            convert(include_str!("../testdata/markdown/text.html")),
+            include_str!("../testdata/markdown/text.md")
+        );
+    }
+
+    #[test]
+    fn test_golden_lists() {
+        assert_eq!(
+            convert(include_str!("../testdata/markdown/lists.html")),
+            include_str!("../testdata/markdown/lists.md")
+        );
+    }
+
+    #[test]
+    fn test_golden_code() {
+        assert_eq!(
+            convert(include_str!("../testdata/markdown/code.html")),
+            include_str!("../testdata/markdown/code.md")
+        );
+    }
+
+    #[test]
+    fn test_golden_tables() {
+        assert_eq!(
+            convert(include_str!("../testdata/markdown/tables.html")),
+            include_str!("../testdata/markdown/tables.md")
+        );
+    }
+
+    #[test]
+    fn test_golden_highlighting() {
+        assert_eq!(
+            convert(include_str!("../testdata/markdown/highlighting.html")),
+            include_str!("../testdata/markdown/highlighting.md")
+        );
+    }
+
+    #[test]
+    fn test_golden_tabs() {
+        assert_eq!(
+            convert(include_str!("../testdata/markdown/tabs.html")),
+            include_str!("../testdata/markdown/tabs.md")
+        );
+    }
+
+    #[test]
+    fn test_golden_admonitions() {
+        assert_eq!(
+            convert(include_str!("../testdata/markdown/admonitions.html")),
+            include_str!("../testdata/markdown/admonitions.md")
+        );
+    }
+
+    #[test]
+    fn test_admonition_styles() {
+        let html = r#"<div class="admonition warning"><p class="admonition-title">Back up first</p><p>Cannot be undone.</p><div class="admonition note"><p>Nested.</p></div></div>"#;
+        let document = Html::parse_fragment(html);
+        let convert_as = |style| {
+            Converter::new("https://example.com/", &HashMap::new(), style)
+                .convert(&document.root_element())
+        };
+        assert_eq!(
+            convert_as(AdmonitionStyle::Mkdocs),
+            "!!! warning \"Back up first\"\n\n    Cannot be undone.\n\n    !!! note\n\n        Nested.\n"
+        );
+        assert_eq!(
+            convert_as(AdmonitionStyle::Docusaurus),
+            "::::warning[Back up first]\n\nCannot be undone.\n\n:::note\n\nNested.\n\n:::\n\n::::\n"
+        );
+    }
+
+    #[test]
+    fn test_escape_line_start() {
+        assert_eq!(escape_line_start("# not a heading"), "\\# not a heading");
+        assert_eq!(escape_line_start("#hashtag"), "#hashtag");
+        assert_eq!(escape_line_start("- dash"), "\\- dash");
+        assert_eq!(escape_line_start("==="), "\\===");
+        assert_eq!(escape_line_start("2) second"), "2\\) second");
+        assert_eq!(escape_line_start("2024 was a year"), "2024 was a year");
+    }
+
+    #[test]
+    fn test_images_are_replaced() {
+        let document = Html::parse_fragment(r#"<p>See <img src="a.png" alt="A"> here</p>"#);
+        let img = document
+            .root_element()
+            .descendants()
+            .find(|node| ElementRef::wrap(*node).is_some_and(|e| e.value().name() == "img"))
+            .unwrap();
+        let image = Image {
+            src: "images/ab12.png".to_string(),
+            alt: "A".to_string(),
+            title: None,
+        };
+        let images = HashMap::from([(img.id(), Some(image))]);
+        let markdown = Converter::new("https://example.com/", &images, AdmonitionStyle::Gfm)
+            .convert(&document.root_element());
+        assert_eq!(markdown, "See ![A](images/ab12.png) here\n");
+    }
+
+    #[test]
+    fn test_rewrite_prose() {
+        let markdown = "a `a` a\n\n> ````sh\n> a\n> ```\n> ````\n\n``a ` a`` \\`a `a\n";
+        assert_eq!(
+            rewrite_prose(markdown, |prose| prose.replace('a', "b")),
+            "b `a` b\n\n> ````sh\n> a\n> ```\n> ````\n\n``a ` a`` \\`b `b\n"
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::fetcher::{Asset, Conditional, Fetcher, Validators};
+use crate::url_reader::Crawler;
+use async_trait::async_trait;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use url::Url;
+
+/// Upper bound for a single backoff wait, whatever the attempt number.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+struct HostBucket {
+    /// Requests per second for this host; infinite when unlimited.
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+    blocked_until: Option<Instant>,
+}
+
+/// Per-host token bucket shared by every fetcher of a crawl.
+///
+/// Each host gets `requests_per_second` tokens per second, with a burst of at
+/// most one second's worth of requests. A host's robots.txt `Crawl-delay` can
+/// lower its rate further.
+pub struct RateLimiter {
+    requests_per_second: f64,
+    hosts: Mutex<HashMap<String, HostBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64) -> Self {
+        RateLimiter {
+            requests_per_second,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn default_rate(&self) -> f64 {
+        if self.requests_per_second > 0.0 {
+            self.requests_per_second
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    fn bucket<'a>(
+        &self,
+        hosts: &'a mut HashMap<String, HostBucket>,
+        host: &str,
+        now: Instant,
+    ) -> &'a mut HostBucket {
+        let rate = self.default_rate();
+        hosts.entry(host.to_string()).or_insert(HostBucket {
+            rate,
+            tokens: 1.0,
+            last_refill: now,
+            blocked_until: None,
+        })
+    }
+
+    /// Waits until a request to the host of `url` is allowed.
+    pub async fn acquire(&self, url: &str) {
+        let host = host_of(url);
+        loop {
+            let wait = self.try_acquire(&host, Instant::now());
+            match wait {
+                None => return,
+                Some(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+
+    /// Takes a token for `host` if one is available, otherwise returns how long to wait.
+    fn try_acquire(&self, host: &str, now: Instant) -> Option<Duration> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let bucket = self.bucket(&mut hosts, host, now);
+
+        if let Some(until) = bucket.blocked_until {
+            if until > now {
+                return Some(until - now);
+            }
+            bucket.blocked_until = None;
+        }
+
+        if bucket.rate.is_infinite() {
+            return None;
+        }
+
+        let capacity = bucket.rate.max(1.0);
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * bucket.rate).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            let missing = 1.0 - bucket.tokens;
+            Some(Duration::from_secs_f64(missing / bucket.rate))
+        }
+    }
+
+    /// Holds back every request to the host of `url` for `delay`.
+    pub fn defer(&self, url: &str, delay: Duration) {
+        let now = Instant::now();
+        let until = now + delay;
+        let mut hosts = self.hosts.lock().unwrap();
+        let bucket = self.bucket(&mut hosts, &host_of(url), now);
+        if bucket.blocked_until.is_none_or(|current| current < until) {
+            bucket.blocked_until = Some(until);
+        }
+    }
+
+    /// Spaces requests to `host` at least `delay` apart, if that is slower than
+    /// the configured rate.
+    pub fn set_crawl_delay(&self, host: &str, delay: Duration) {
+        if delay.is_zero() {
+            return;
+        }
+        let mut hosts = self.hosts.lock().unwrap();
+        let bucket = self.bucket(&mut hosts, host, Instant::now());
+        let rate = 1.0 / delay.as_secs_f64();
+        if rate < bucket.rate {
+            bucket.rate = rate;
+            bucket.tokens = bucket.tokens.min(1.0);
+        }
+    }
+}
+
+fn host_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Parses a `Retry-After` header, given either as seconds or as an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Exponential backoff for the given retry attempt (starting at 0), with up to
+/// 50% random jitter either way so that parallel workers do not retry in lockstep.
+fn backoff(base: Duration, attempt: u32) -> Duration {
+    let exponential = base
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF);
+    let jitter = rand::thread_rng().gen_range(0.5..1.5);
+    exponential.mul_f64(jitter)
+}
+
+/// Whether `error` is a throttling or server error worth retrying after a pause.
+pub fn is_retryable(error: &Crawler) -> bool {
+    matches!(error, Crawler::Http { status, .. } if *status == 429 || *status >= 500)
+}
+
+/// Wraps a fetcher with per-host rate limiting and retries on 429 and 5xx responses.
+pub struct PoliteFetcher {
+    inner: Box<dyn Fetcher>,
+    limiter: Arc<RateLimiter>,
+    max_retries: u32,
+    base_delay: Duration,
+}
+
+impl PoliteFetcher {
+    pub fn new(inner: Box<dyn Fetcher>, limiter: Arc<RateLimiter>, max_retries: u32) -> Self {
+        PoliteFetcher {
+            inner,
+            limiter,
+            max_retries,
+            base_delay: Duration::from_secs(1),
+        }
+    }
+
+    async fn with_retries<T, F, Fut>(&self, url: &str, mut request: F) -> Result<T, Crawler>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: std::future::Future<Output = Result<T, Crawler>> + Send,
+    {
+        let mut attempt = 0;
+        loop {
+            self.limiter.acquire(url).await;
+            match request().await {
+                Err(e) if is_retryable(&e) && attempt < self.max_retries => {
+                    let mut delay = backoff(self.base_delay, attempt);
+                    if let Crawler::Http {
+                        retry_after: Some(retry_after),
+                        ..
+                    } = &e
+                    {
+                        delay = delay.max(*retry_after);
+                    }
+                    println!("{}; retrying in {:.1}s", e, delay.as_secs_f64());
+                    self.limiter.defer(url, delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Fetcher for PoliteFetcher {
+    async fn fetch_html(&self, url: &str) -> Result<String, Crawler> {
+        self.with_retries(url, || self.inner.fetch_html(url)).await
+    }
+
+    async fn fetch_bytes(&self, url: &str) -> Result<Vec<u8>, Crawler> {
+        self.with_retries(url, || self.inner.fetch_bytes(url)).await
+    }
+
+    async fn fetch_asset(&self, url: &str, max_size: usize) -> Result<Asset, Crawler> {
+        self.with_retries(url, || self.inner.fetch_asset(url, max_size))
+            .await
+    }
+
+    async fn fetch_html_if_modified(
+        &self,
+        url: &str,
+        known: &Validators,
+    ) -> Result<Conditional, Crawler> {
+        self.with_retries(url, || self.inner.fetch_html_if_modified(url, known))
+            .await
+    }
+
+    async fn close(self: Box<Self>) {
+        self.inner.close().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_spaces_requests_per_host() {
+        let limiter = RateLimiter::new(2.0);
+        let start = Instant::now();
+
+        assert_eq!(limiter.try_acquire("a.com", start), None);
+        let wait = limiter.try_acquire("a.com", start).unwrap();
+        assert_eq!(wait, Duration::from_millis(500));
+        assert_eq!(limiter.try_acquire("b.com", start), None);
+        assert_eq!(
+            limiter.try_acquire("a.com", start + Duration::from_millis(500)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_crawl_delay_slows_a_single_host() {
+        let limiter = RateLimiter::new(0.0);
+        let start = Instant::now();
+        limiter.set_crawl_delay("a.com", Duration::from_secs(5));
+
+        assert_eq!(limiter.try_acquire("a.com", start), None);
+        assert_eq!(
+            limiter.try_acquire("a.com", start),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(limiter.try_acquire("b.com", start), None);
+        assert_eq!(limiter.try_acquire("b.com", start), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let base = Duration::from_secs(1);
+        let first = backoff(base, 0);
+        assert!(first >= Duration::from_millis(500) && first <= Duration::from_millis(1500));
+        let third = backoff(base, 2);
+        assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(6));
+        assert!(backoff(base, 20) <= MAX_BACKOFF.mul_f64(1.5));
+    }
+}
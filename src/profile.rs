@@ -0,0 +1,391 @@
+use crate::expand::{AutoScroll, Interaction};
+use crate::markdown::AdmonitionStyle;
+use crate::wait::WaitStrategy;
+use clap::ValueEnum;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+
+/// A documentation generator the crawler knows how to read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileName {
+    /// Detect the generator from the start page
+    Auto,
+    /// Any site: common content containers and the first `<h1>`
+    Generic,
+    Gitbook,
+    Docusaurus,
+    /// MkDocs, with or without the Material theme
+    Mkdocs,
+    /// Sphinx, including Read the Docs themes
+    Sphinx,
+    Vitepress,
+    Mdbook,
+}
+
+/// Where the content of a documentation site's pages lives and what around it
+/// is page chrome.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub name: String,
+    /// Elements holding the page content, tried in order until one matches
+    pub content: Vec<String>,
+    /// Elements dropped from the page before conversion, such as edit links,
+    /// feedback widgets and previous/next buttons
+    pub remove: Vec<String>,
+    /// Elements whose text is the page title, tried in order
+    pub title: Vec<String>,
+    /// Navigation trees whose links are followed first, in reading order
+    pub nav: Vec<String>,
+    /// Elements whose links are followed besides the navigation trees; every
+    /// link on the page is followed when this is empty
+    pub follow: Vec<String>,
+    /// Conditions the browser backend waits for before reading a rendered page
+    pub wait: Vec<WaitStrategy>,
+    /// Steps the browser backend runs to reveal hidden content before reading
+    /// a rendered page
+    pub expand: Vec<Interaction>,
+    /// Whether and how the browser backend scrolls through a rendered page
+    /// before reading it
+    pub scroll: AutoScroll,
+    /// Rewrites applied to the extracted markdown, in order
+    pub replace: Vec<Replacement>,
+    /// How notes, tips and warnings are written in the markdown
+    pub admonitions: AdmonitionStyle,
+}
+
+/// A regular expression rewrite of the extracted markdown. Patterns run in
+/// multi-line mode, so `^` and `$` match at every line.
+#[derive(Clone, Debug)]
+pub struct Replacement {
+    pub pattern: Regex,
+    /// Replacement text, which may refer to groups as `$1` or `${name}`
+    pub with: String,
+}
+
+impl Replacement {
+    pub fn new(pattern: &str, with: &str) -> Result<Self, regex::Error> {
+        Ok(Replacement {
+            pattern: Regex::new(&format!("(?m){}", pattern))?,
+            with: with.to_string(),
+        })
+    }
+}
+
+impl Profile {
+    /// Applies the profile's replacements to `markdown`.
+    pub fn rewrite(&self, markdown: &str) -> String {
+        self.replace
+            .iter()
+            .fold(markdown.to_string(), |text, replacement| {
+                replacement
+                    .pattern
+                    .replace_all(&text, replacement.with.as_str())
+                    .into_owned()
+            })
+    }
+}
+
+/// Generators named in `<meta name="generator">`, matched case-insensitively.
+const GENERATOR_NAMES: &[(&str, ProfileName)] = &[
+    ("gitbook", ProfileName::Gitbook),
+    ("docusaurus", ProfileName::Docusaurus),
+    ("mkdocs", ProfileName::Mkdocs),
+    ("sphinx", ProfileName::Sphinx),
+    ("vitepress", ProfileName::Vitepress),
+    ("mdbook", ProfileName::Mdbook),
+];
+
+/// Markup each generator leaves in its pages, for sites without a generator tag.
+const FINGERPRINTS: &[(&str, ProfileName)] = &[
+    ("#__docusaurus, .theme-doc-markdown", ProfileName::Docusaurus),
+    (".md-content, .md-container", ProfileName::Mkdocs),
+    (
+        "script[src*='_static/doctools.js'], script[src*='_static/documentation_options.js'], .wy-nav-content, div.sphinxsidebar",
+        ProfileName::Sphinx,
+    ),
+    ("#VPContent, .VPDoc, .vp-doc", ProfileName::Vitepress),
+    ("#mdbook-help-container, .sidebar-scrollbox, #menu-bar", ProfileName::Mdbook),
+    (
+        ".book-summary, .gitbook-root, [class*='gitbook']",
+        ProfileName::Gitbook,
+    ),
+];
+
+/// Content containers tried on sites no profile matches.
+const GENERIC_CONTENT: &[&str] = &[
+    "div[role='main']",
+    "main",
+    "div.content",
+    "div.markdown",
+    "div.page-inner",
+    "div#content",
+    "div.documentation",
+    "article",
+    "div[class*='content']",
+    "div[class*='markdown']",
+];
+
+/// The selectors of a built-in [`Profile`].
+struct Builtin {
+    content: &'static [&'static str],
+    remove: &'static [&'static str],
+    title: &'static [&'static str],
+    nav: &'static [&'static str],
+    wait: &'static [&'static str],
+}
+
+impl ProfileName {
+    /// The built-in profile with this name, or `None` for [`ProfileName::Auto`].
+    pub fn profile(self) -> Option<Profile> {
+        let builtin = match self {
+            ProfileName::Auto => return None,
+            ProfileName::Generic => Builtin {
+                content: GENERIC_CONTENT,
+                remove: &[],
+                title: &["h1"],
+                nav: &[],
+                wait: &["load", "dom-stable"],
+            },
+            ProfileName::Gitbook => Builtin {
+                content: &[
+                    "section.markdown-section",
+                    "div.page-inner",
+                    "main",
+                    "div[class*='markdown']",
+                ],
+                remove: &[
+                    "a.navigation",
+                    ".page-footer",
+                    "[class*='page-footer']",
+                    "[class*='feedback']",
+                    "[class*='pagination']",
+                    "[class*='last-modified']",
+                ],
+                title: &["main h1", "h1"],
+                nav: &[".book-summary ul.summary", "aside nav", "aside"],
+                wait: &["selector:main h1, section.markdown-section", "dom-stable"],
+            },
+            ProfileName::Docusaurus => Builtin {
+                content: &[".theme-doc-markdown", "article", "main"],
+                remove: &[
+                    ".theme-doc-footer",
+                    ".theme-edit-this-page",
+                    ".theme-last-updated",
+                    ".pagination-nav",
+                    ".theme-doc-breadcrumbs",
+                    ".theme-doc-toc-mobile",
+                    ".theme-doc-version-badge",
+                    ".theme-doc-version-banner",
+                    "a.hash-link",
+                ],
+                title: &["article header h1", ".theme-doc-markdown h1", "h1"],
+                nav: &[".theme-doc-sidebar-menu", "nav.menu"],
+                wait: &["selector:.theme-doc-markdown"],
+            },
+            ProfileName::Mkdocs => Builtin {
+                content: &[
+                    "article.md-content__inner",
+                    ".md-content",
+                    "div[role='main']",
+                ],
+                remove: &[
+                    ".md-content__button",
+                    ".md-source-file",
+                    ".md-feedback",
+                    ".md-footer",
+                    "a.headerlink",
+                ],
+                title: &["article h1", "h1"],
+                nav: &[".md-nav--primary", ".wy-menu-vertical"],
+                wait: &["load"],
+            },
+            ProfileName::Sphinx => Builtin {
+                content: &[
+                    "div[itemprop='articleBody']",
+                    "article[role='main']",
+                    "article.bd-article",
+                    "div.body",
+                    "main",
+                ],
+                remove: &[
+                    "a.headerlink",
+                    ".rst-footer-buttons",
+                    ".prev-next-area",
+                    ".related-pages",
+                    ".edit-this-page",
+                    ".wy-breadcrumbs",
+                    "div.related",
+                ],
+                title: &["div[itemprop='articleBody'] h1", "article h1", "h1"],
+                nav: &[
+                    ".wy-menu-vertical",
+                    ".sidebar-tree",
+                    "nav.bd-docs-nav",
+                    "div.sphinxsidebarwrapper",
+                ],
+                wait: &["load"],
+            },
+            ProfileName::Vitepress => Builtin {
+                content: &[".vp-doc", "main"],
+                remove: &[
+                    ".VPDocFooter",
+                    ".edit-info",
+                    ".edit-link",
+                    ".prev-next",
+                    "a.header-anchor",
+                    "div[class*='language-'] > span.lang",
+                ],
+                title: &[".vp-doc h1", "h1"],
+                nav: &["#VPSidebarNav", ".VPSidebar nav"],
+                wait: &["selector:.vp-doc"],
+            },
+            ProfileName::Mdbook => Builtin {
+                content: &["#content main", "main"],
+                remove: &[".nav-chapters", ".mobile-nav-chapters", "#menu-bar"],
+                title: &["main h1", "h1"],
+                nav: &["#sidebar .chapter", "#sidebar"],
+                wait: &["load"],
+            },
+        };
+
+        let strings = |list: &[&str]| list.iter().map(|s| s.to_string()).collect();
+        Some(Profile {
+            name: self
+                .to_possible_value()
+                .map_or_else(String::new, |value| value.get_name().to_string()),
+            content: strings(builtin.content),
+            remove: strings(builtin.remove),
+            title: strings(builtin.title),
+            nav: strings(builtin.nav),
+            follow: Vec::new(),
+            wait: builtin
+                .wait
+                .iter()
+                .map(|strategy| {
+                    strategy
+                        .parse()
+                        .expect("built-in wait strategies are valid")
+                })
+                .collect(),
+            expand: Vec::new(),
+            scroll: AutoScroll::default(),
+            replace: Vec::new(),
+            admonitions: AdmonitionStyle::default(),
+        })
+    }
+}
+
+/// Picks the profile for a site from the HTML of one of its pages: the
+/// `<meta name="generator">` tag when it names a known generator, otherwise
+/// markup only that generator produces, otherwise [`ProfileName::Generic`].
+pub fn detect(html: &str) -> ProfileName {
+    let document = Html::parse_document(html);
+
+    let generator_selector = Selector::parse("meta[name='generator' i][content]").unwrap();
+    for meta in document.select(&generator_selector) {
+        let generator = meta.value().attr("content").unwrap_or("").to_lowercase();
+        if let Some((_, name)) = GENERATOR_NAMES
+            .iter()
+            .find(|(needle, _)| generator.contains(needle))
+        {
+            return *name;
+        }
+    }
+
+    // mdBook only identifies itself in a comment
+    if html.contains("Book generated using mdBook") {
+        return ProfileName::Mdbook;
+    }
+
+    FINGERPRINTS
+        .iter()
+        .find(|(selector, _)| {
+            Selector::parse(selector)
+                .map(|selector| document.select(&selector).next().is_some())
+                .unwrap_or(false)
+        })
+        .map_or(ProfileName::Generic, |(_, name)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_from_generator_meta() {
+        let cases = [
+            ("Docusaurus v3.1.0", ProfileName::Docusaurus),
+            ("mkdocs-1.5.3, mkdocs-material-9.4.8", ProfileName::Mkdocs),
+            ("Sphinx 7.2.6", ProfileName::Sphinx),
+            ("VitePress v1.0.0", ProfileName::Vitepress),
+            ("GitBook 3.2.3", ProfileName::Gitbook),
+        ];
+        for (generator, expected) in cases {
+            let html = format!(
+                r#"<html><head><meta name="generator" content="{}"></head><body></body></html>"#,
+                generator
+            );
+            assert_eq!(detect(&html), expected, "{}", generator);
+        }
+    }
+
+    #[test]
+    fn test_detect_from_markup() {
+        assert_eq!(
+            detect(r#"<div id="__docusaurus"><main></main></div>"#),
+            ProfileName::Docusaurus
+        );
+        assert_eq!(
+            detect(r#"<head><script src="../_static/doctools.js"></script></head>"#),
+            ProfileName::Sphinx
+        );
+        assert_eq!(
+            detect("<!DOCTYPE HTML>\n<!-- Book generated using mdBook -->\n<html></html>"),
+            ProfileName::Mdbook
+        );
+        assert_eq!(
+            detect("<main><h1>Plain site</h1></main>"),
+            ProfileName::Generic
+        );
+    }
+
+    #[test]
+    fn test_builtin_profiles_have_valid_selectors() {
+        for name in ProfileName::value_variants() {
+            let Some(profile) = name.profile() else {
+                continue;
+            };
+            let selectors = profile
+                .content
+                .iter()
+                .chain(&profile.remove)
+                .chain(&profile.title)
+                .chain(&profile.nav);
+            for selector in selectors {
+                assert!(
+                    Selector::parse(selector).is_ok(),
+                    "{}: {}",
+                    profile.name,
+                    selector
+                );
+            }
+        }
+        assert!(ProfileName::Auto.profile().is_none());
+        assert_eq!(ProfileName::Mkdocs.profile().unwrap().name, "mkdocs");
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let mut profile = ProfileName::Generic.profile().unwrap();
+        profile.replace = vec![
+            Replacement::new(r"^Last updated on .*\n", "").unwrap(),
+            Replacement::new(r"ACME Corp\b", "ACME").unwrap(),
+        ];
+        assert_eq!(
+            profile.rewrite("# Intro\nLast updated on 2024-01-02\nBy ACME Corp.\n"),
+            "# Intro\nBy ACME.\n"
+        );
+    }
+}
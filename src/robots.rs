@@ -0,0 +1,315 @@
+use crate::fetcher::{Fetcher, HttpFetcher};
+use crate::politeness::{PoliteFetcher, RateLimiter};
+use crate::url_reader::Crawler;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use url::Url;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+/// The parts of a robots.txt file that apply to one user agent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Robots {
+    rules: Vec<Rule>,
+    pub crawl_delay: Option<Duration>,
+    pub sitemaps: Vec<String>,
+}
+
+impl Robots {
+    /// A policy that allows everything, used when a host has no robots.txt.
+    pub fn allow_all() -> Self {
+        Robots::default()
+    }
+
+    /// A policy that allows nothing, used when robots.txt cannot be retrieved.
+    pub fn disallow_all() -> Self {
+        Robots {
+            rules: vec![Rule {
+                allow: false,
+                pattern: "/".to_string(),
+            }],
+            ..Robots::default()
+        }
+    }
+
+    /// Parses `text` and keeps the group that best matches `user_agent`.
+    ///
+    /// A group applies when its `User-agent` value is contained in the product
+    /// token of `user_agent` (the part before the first `/`). The longest such
+    /// value wins; `*` is only used when nothing more specific matches.
+    pub fn parse(text: &str, user_agent: &str) -> Self {
+        let product = user_agent
+            .split('/')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_lowercase();
+
+        let mut sitemaps = Vec::new();
+        // (agents, rules, crawl delay) for each group, in file order
+        let mut groups: Vec<(Vec<String>, Vec<Rule>, Option<Duration>)> = Vec::new();
+        let mut in_agent_lines = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim().to_lowercase();
+            let value = value.trim();
+
+            match key.as_str() {
+                "user-agent" => {
+                    if !in_agent_lines {
+                        groups.push((Vec::new(), Vec::new(), None));
+                        in_agent_lines = true;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.0.push(value.to_lowercase());
+                    }
+                }
+                "allow" | "disallow" => {
+                    in_agent_lines = false;
+                    if let Some(group) = groups.last_mut() {
+                        // An empty Disallow allows everything and adds no rule
+                        if !value.is_empty() {
+                            group.1.push(Rule {
+                                allow: key == "allow",
+                                pattern: value.to_string(),
+                            });
+                        }
+                    }
+                }
+                "crawl-delay" => {
+                    in_agent_lines = false;
+                    if let (Some(group), Ok(seconds)) = (groups.last_mut(), value.parse::<f64>()) {
+                        if seconds >= 0.0 {
+                            group.2 = Some(Duration::from_secs_f64(seconds));
+                        }
+                    }
+                }
+                "sitemap" => sitemaps.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        let specificity = |agent: &str| -> Option<usize> {
+            if agent == "*" {
+                Some(0)
+            } else if !product.is_empty() && product.contains(agent) {
+                Some(agent.len())
+            } else {
+                None
+            }
+        };
+
+        let best = groups
+            .iter()
+            .filter_map(|(agents, _, _)| agents.iter().filter_map(|a| specificity(a)).max())
+            .max();
+
+        let mut robots = Robots {
+            sitemaps,
+            ..Robots::default()
+        };
+        if let Some(best) = best {
+            // Groups naming the same agent are merged
+            for (agents, rules, crawl_delay) in groups {
+                if agents.iter().any(|a| specificity(a) == Some(best)) {
+                    robots.rules.extend(rules);
+                    if crawl_delay.is_some() {
+                        robots.crawl_delay = crawl_delay;
+                    }
+                }
+            }
+        }
+        robots
+    }
+
+    /// Whether `path` (path plus query) may be crawled.
+    ///
+    /// The longest matching rule decides; on a tie `Allow` wins.
+    pub fn is_allowed(&self, path: &str) -> bool {
+        let mut best: Option<(usize, bool)> = None;
+        for rule in &self.rules {
+            if pattern_matches(&rule.pattern, path) {
+                let len = rule.pattern.len();
+                best = match best {
+                    Some((best_len, best_allow))
+                        if best_len > len || (best_len == len && best_allow) =>
+                    {
+                        Some((best_len, best_allow))
+                    }
+                    _ => Some((len, rule.allow)),
+                };
+            }
+        }
+        best.is_none_or(|(_, allow)| allow)
+    }
+}
+
+/// Matches a robots.txt path pattern, where `*` is any run of characters and a
+/// trailing `$` anchors the end of the path.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let Some(rest) = path.strip_prefix(parts[0]) else {
+        return false;
+    };
+
+    let mut rest = rest;
+    for (i, part) in parts.iter().enumerate().skip(1) {
+        let is_last = i == parts.len() - 1;
+        if is_last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+/// Fetches and caches robots.txt for every host the crawl touches, through
+/// the crawl's rate limiter.
+pub struct RobotsCache {
+    user_agent: String,
+    fetcher: PoliteFetcher,
+    limiter: Arc<RateLimiter>,
+    hosts: Mutex<HashMap<String, Arc<Robots>>>,
+}
+
+impl RobotsCache {
+    pub fn new(user_agent: &str, limiter: Arc<RateLimiter>, max_retries: u32) -> Self {
+        RobotsCache {
+            user_agent: user_agent.to_string(),
+            fetcher: PoliteFetcher::new(
+                Box::new(HttpFetcher::new(user_agent)),
+                limiter.clone(),
+                max_retries,
+            ),
+            limiter,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the robots.txt policy for the host of `url`, fetching it on first use.
+    pub async fn get(&self, url: &Url) -> Arc<Robots> {
+        let origin = url.origin().ascii_serialization();
+        if let Some(robots) = self.hosts.lock().unwrap().get(&origin) {
+            return robots.clone();
+        }
+
+        let robots_url = format!("{}/robots.txt", origin);
+        let robots = match self.fetcher.fetch_html(&robots_url).await {
+            Ok(text) => Robots::parse(&text, &self.user_agent),
+            // A missing robots.txt allows everything, but a host still
+            // throttling after every retry is not read as permission
+            Err(Crawler::Http { status, .. }) if (400..500).contains(&status) && status != 429 => {
+                Robots::allow_all()
+            }
+            Err(e) => {
+                println!(
+                    "Could not read {} ({}); treating the host as disallowed",
+                    robots_url, e
+                );
+                Robots::disallow_all()
+            }
+        };
+
+        if let Some(delay) = robots.crawl_delay {
+            if let Some(host) = url.host_str() {
+                println!("Applying Crawl-delay of {:?} to {}", delay, host);
+                self.limiter.set_crawl_delay(host, delay);
+            }
+        }
+
+        let robots = Arc::new(robots);
+        self.hosts.lock().unwrap().insert(origin, robots.clone());
+        robots
+    }
+
+    /// Whether robots.txt allows the crawler to fetch `url`.
+    pub async fn is_allowed(&self, url: &str) -> bool {
+        let Ok(parsed) = Url::parse(url) else {
+            return false;
+        };
+        let robots = self.get(&parsed).await;
+
+        let mut path = parsed.path().to_string();
+        if let Some(query) = parsed.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        robots.is_allowed(&path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "
+# Example
+User-agent: *
+Disallow: /private/
+Crawl-delay: 10
+
+User-agent: web-doc-crawler
+User-agent: other-bot
+Disallow: /drafts/
+Allow: /drafts/public$
+Disallow: /*.pdf$
+Crawl-delay: 2.5
+
+Sitemap: https://example.com/sitemap.xml
+";
+
+    #[test]
+    fn test_parse_picks_most_specific_group() {
+        let robots = Robots::parse(ROBOTS, "web-doc-crawler/0.1");
+        assert_eq!(robots.crawl_delay, Some(Duration::from_millis(2500)));
+        assert_eq!(robots.sitemaps, vec!["https://example.com/sitemap.xml"]);
+        assert!(robots.is_allowed("/private/notes"));
+        assert!(!robots.is_allowed("/drafts/wip"));
+        assert!(robots.is_allowed("/drafts/public"));
+        assert!(!robots.is_allowed("/drafts/public/more"));
+        assert!(!robots.is_allowed("/guide/manual.pdf"));
+        assert!(robots.is_allowed("/guide/manual.pdf?download=1"));
+    }
+
+    #[test]
+    fn test_parse_falls_back_to_wildcard_group() {
+        let robots = Robots::parse(ROBOTS, "SomeOtherCrawler/2.0");
+        assert_eq!(robots.crawl_delay, Some(Duration::from_secs(10)));
+        assert!(!robots.is_allowed("/private/notes"));
+        assert!(robots.is_allowed("/drafts/wip"));
+    }
+
+    #[test]
+    fn test_empty_disallow_allows_everything() {
+        let robots = Robots::parse("User-agent: *\nDisallow:\n", "web-doc-crawler");
+        assert!(robots.is_allowed("/anything"));
+        assert!(!Robots::disallow_all().is_allowed("/anything"));
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches("/docs", "/docs/intro"));
+        assert!(pattern_matches("/*/edit", "/guide/edit?x=1"));
+        assert!(pattern_matches("/*.html$", "/a/b.html"));
+        assert!(!pattern_matches("/*.html$", "/a/b.html?x"));
+        assert!(!pattern_matches("/docs", "/blog/docs"));
+    }
+}
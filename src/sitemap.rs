@@ -0,0 +1,277 @@
+use crate::fetcher::Fetcher;
+use flate2::read::GzDecoder;
+use regex::Regex;
+use std::collections::HashSet;
+use std::io::Read;
+use url::Url;
+
+/// Sitemap indexes can nest; stop following them after this many files.
+const MAX_SITEMAPS: usize = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapEntry {
+    pub loc: String,
+    pub lastmod: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Sitemap {
+    /// Pages listed in a `<urlset>`
+    pub urls: Vec<SitemapEntry>,
+    /// Child sitemaps listed in a `<sitemapindex>`
+    pub sitemaps: Vec<SitemapEntry>,
+}
+
+/// Parses a `<urlset>` or `<sitemapindex>` document.
+pub fn parse_sitemap(xml: &str) -> Sitemap {
+    let url_regex = Regex::new(r"(?s)<url>(.*?)</url>").unwrap();
+    let sitemap_regex = Regex::new(r"(?s)<sitemap>(.*?)</sitemap>").unwrap();
+    let loc_regex = Regex::new(r"(?s)<loc>(.*?)</loc>").unwrap();
+    let lastmod_regex = Regex::new(r"(?s)<lastmod>(.*?)</lastmod>").unwrap();
+
+    let entries = |block_regex: &Regex| -> Vec<SitemapEntry> {
+        block_regex
+            .captures_iter(xml)
+            .filter_map(|block| {
+                let block = block.get(1)?.as_str();
+                let loc = xml_text(loc_regex.captures(block)?.get(1)?.as_str());
+                let lastmod = lastmod_regex
+                    .captures(block)
+                    .and_then(|c| c.get(1))
+                    .map(|m| xml_text(m.as_str()));
+                Some(SitemapEntry { loc, lastmod })
+            })
+            .collect()
+    };
+
+    Sitemap {
+        urls: entries(&url_regex),
+        sitemaps: entries(&sitemap_regex),
+    }
+}
+
+/// Unwraps CDATA and decodes the predefined XML entities.
+fn xml_text(raw: &str) -> String {
+    let raw = raw.trim();
+    if let Some(cdata) = raw
+        .strip_prefix("<![CDATA[")
+        .and_then(|r| r.strip_suffix("]]>"))
+    {
+        return cdata.trim().to_string();
+    }
+    raw.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+/// Decompresses gzipped sitemaps, detected by their magic bytes rather than
+/// the `.gz` extension since servers are inconsistent about both.
+fn decode(bytes: Vec<u8>) -> Option<String> {
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut xml = String::new();
+        GzDecoder::new(bytes.as_slice())
+            .read_to_string(&mut xml)
+            .ok()?;
+        Some(xml)
+    } else {
+        String::from_utf8(bytes).ok()
+    }
+}
+
+/// Collects every page listed in the sitemaps of `start_url`'s host.
+///
+/// Starts from `/sitemap.xml` plus any sitemaps announced in robots.txt and
+/// follows nested sitemap indexes. Pages come back newest `<lastmod>` first,
+/// with undated pages last, so that the most recently changed documentation is
+/// crawled before the page limit is reached.
+pub async fn discover(
+    fetcher: &dyn Fetcher,
+    start_url: &str,
+    announced: &[String],
+) -> Vec<SitemapEntry> {
+    let Ok(start) = Url::parse(start_url) else {
+        return Vec::new();
+    };
+
+    let mut pending: Vec<String> = announced.to_vec();
+    pending.push(format!(
+        "{}/sitemap.xml",
+        start.origin().ascii_serialization()
+    ));
+    pending.reverse();
+
+    let mut seen = HashSet::new();
+    let mut pages = Vec::new();
+
+    while let Some(sitemap_url) = pending.pop() {
+        if !seen.insert(sitemap_url.clone()) {
+            continue;
+        }
+        if seen.len() > MAX_SITEMAPS {
+            println!("Stopping sitemap discovery after {} sitemaps", MAX_SITEMAPS);
+            break;
+        }
+
+        let xml = match fetcher.fetch_bytes(&sitemap_url).await {
+            Ok(bytes) => match decode(bytes) {
+                Some(xml) => xml,
+                None => {
+                    println!("Could not decode sitemap {}", sitemap_url);
+                    continue;
+                }
+            },
+            Err(e) => {
+                println!("No sitemap at {}: {}", sitemap_url, e);
+                continue;
+            }
+        };
+
+        let sitemap = parse_sitemap(&xml);
+        println!(
+            "Read sitemap {} ({} pages, {} nested sitemaps)",
+            sitemap_url,
+            sitemap.urls.len(),
+            sitemap.sitemaps.len()
+        );
+        pending.extend(sitemap.sitemaps.into_iter().rev().map(|entry| entry.loc));
+        pages.extend(sitemap.urls);
+    }
+
+    sort_by_lastmod(&mut pages);
+    pages
+}
+
+/// Orders entries newest first; undated entries keep their order at the end.
+fn sort_by_lastmod(entries: &mut [SitemapEntry]) {
+    entries.sort_by(|a, b| b.lastmod.cmp(&a.lastmod));
+}
+
+/// Checks that `value` is a real calendar date written as `YYYY-MM-DD`, the
+/// form [`modified_since`] compares `<lastmod>` against.
+pub fn parse_date(value: &str) -> Result<String, String> {
+    let invalid = || format!("{:?} is not a date in the form YYYY-MM-DD", value);
+    let bytes = value.as_bytes();
+    let digits = |range: std::ops::Range<usize>| {
+        bytes[range.clone()]
+            .iter()
+            .all(u8::is_ascii_digit)
+            .then(|| value[range].parse::<u32>().ok())
+            .flatten()
+    };
+    if bytes.len() != 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return Err(invalid());
+    }
+    let (Some(year), Some(month), Some(day)) = (digits(0..4), digits(5..7), digits(8..10)) else {
+        return Err(invalid());
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return Err(invalid()),
+    };
+    if day == 0 || day > days {
+        return Err(invalid());
+    }
+    Ok(value.to_string())
+}
+
+/// Whether `entry` changed on or after `since` (a `YYYY-MM-DD` date).
+/// Entries without `<lastmod>` are assumed to have changed.
+pub fn modified_since(entry: &SitemapEntry, since: &str) -> bool {
+    match &entry.lastmod {
+        Some(lastmod) => lastmod.get(..10).unwrap_or(lastmod) >= since,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const URLSET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://example.com/docs/a</loc><lastmod>2024-01-05</lastmod></url>
+  <url>
+    <loc>https://example.com/docs/b?x=1&amp;y=2</loc>
+  </url>
+  <url><loc><![CDATA[https://example.com/docs/c]]></loc><lastmod>2024-03-01T10:00:00+00:00</lastmod></url>
+</urlset>"#;
+
+    #[test]
+    fn test_parse_urlset() {
+        let sitemap = parse_sitemap(URLSET);
+        assert!(sitemap.sitemaps.is_empty());
+        assert_eq!(sitemap.urls.len(), 3);
+        assert_eq!(sitemap.urls[0].lastmod.as_deref(), Some("2024-01-05"));
+        assert_eq!(sitemap.urls[1].loc, "https://example.com/docs/b?x=1&y=2");
+        assert_eq!(sitemap.urls[1].lastmod, None);
+    }
+
+    #[test]
+    fn test_parse_sitemap_index() {
+        let xml = r#"<sitemapindex>
+            <sitemap><loc>https://example.com/sitemap-docs.xml.gz</loc></sitemap>
+        </sitemapindex>"#;
+        let sitemap = parse_sitemap(xml);
+        assert!(sitemap.urls.is_empty());
+        assert_eq!(
+            sitemap.sitemaps[0].loc,
+            "https://example.com/sitemap-docs.xml.gz"
+        );
+    }
+
+    #[test]
+    fn test_decode_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(URLSET.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        assert_eq!(decode(gzipped).as_deref(), Some(URLSET));
+        assert_eq!(decode(b"<urlset/>".to_vec()).as_deref(), Some("<urlset/>"));
+    }
+
+    #[test]
+    fn test_sort_and_filter_by_lastmod() {
+        let mut entries = parse_sitemap(URLSET).urls;
+        sort_by_lastmod(&mut entries);
+        let locs: Vec<_> = entries.iter().map(|e| e.loc.as_str()).collect();
+        assert_eq!(
+            locs,
+            vec![
+                "https://example.com/docs/c",
+                "https://example.com/docs/a",
+                "https://example.com/docs/b?x=1&y=2"
+            ]
+        );
+
+        assert!(modified_since(&entries[0], "2024-02-01"));
+        assert!(!modified_since(&entries[1], "2024-02-01"));
+        assert!(modified_since(&entries[2], "2024-02-01"));
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2024-02-29"), Ok("2024-02-29".to_string()));
+        for value in [
+            "2024-1-5",
+            "5/1/2024",
+            "yesterday",
+            "2023-02-29",
+            "2024-13-01",
+            "2024-04-31",
+            "2024-00-10",
+            "+024-01-05",
+        ] {
+            assert!(parse_date(value).is_err(), "{}", value);
+        }
+    }
+}
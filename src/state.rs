@@ -0,0 +1,298 @@
+use crate::crawler::Frontier;
+use crate::fetcher::Validators;
+use crate::url_reader::Crawler;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the checkpoint file kept in the output directory while a crawl runs.
+pub const STATE_FILE: &str = ".crawl-state.json";
+
+/// Name of the file recording every page of the last completed crawl.
+pub const MANIFEST_FILE: &str = ".crawl-manifest.json";
+
+/// Directory in the output directory holding the markdown extracted from every
+/// page before links are rewritten, named by content hash.
+pub const PAGE_CACHE_DIR: &str = ".crawl-cache";
+
+/// A page that has been fetched and written to disk.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PageRecord {
+    pub url: String,
+    pub title: String,
+    /// Output file, relative to the output directory
+    pub path: String,
+    pub depth: usize,
+    /// SHA-256 of the markdown extracted from the page, which is kept in the
+    /// page cache under this name
+    pub content_hash: String,
+    #[serde(default)]
+    pub validators: Validators,
+    /// In-scope links found on the page, followed again when a recrawl
+    /// finds the page unchanged
+    #[serde(default)]
+    pub links: Vec<String>,
+    /// Heading ids on the page mapped to their markdown slugs, used to
+    /// rewrite `#fragment` links pointing at it
+    #[serde(default)]
+    pub anchors: BTreeMap<String, String>,
+}
+
+/// Everything needed to continue an interrupted crawl with `--resume`.
+#[derive(Serialize, Deserialize)]
+pub struct CrawlState {
+    pub start_url: String,
+    pub frontier: Frontier,
+    pub pages: Vec<PageRecord>,
+    /// Pages taken from the frontier so far, counted against `--max-pages`
+    pub fetched: usize,
+    /// Fetched URLs that were not written because they duplicate another
+    /// page, mapped to the URL of the page that was kept
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl CrawlState {
+    pub fn new(start_url: &str, frontier: Frontier) -> Self {
+        CrawlState {
+            start_url: start_url.to_string(),
+            frontier,
+            pages: Vec::new(),
+            fetched: 0,
+            aliases: BTreeMap::new(),
+        }
+    }
+
+    fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(STATE_FILE)
+    }
+
+    /// Loads the checkpoint from `output_dir`, if there is one.
+    pub fn load(output_dir: &Path) -> Result<Option<Self>, Crawler> {
+        let path = Self::path(output_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&path)?;
+        serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| Crawler::Parsing(format!("Invalid {}: {}", path.display(), e)))
+    }
+
+    /// Writes the checkpoint atomically, so a crash mid-write leaves the
+    /// previous checkpoint intact.
+    pub fn save(&self, output_dir: &Path) -> Result<(), Crawler> {
+        let json =
+            serde_json::to_string_pretty(self).map_err(|e| Crawler::Parsing(e.to_string()))?;
+        let path = Self::path(output_dir);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    /// Removes the checkpoint once the crawl has finished.
+    pub fn remove(output_dir: &Path) -> Result<(), Crawler> {
+        let path = Self::path(output_dir);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// The pages of the last completed crawl, used to recrawl incrementally.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub pages: Vec<PageRecord>,
+}
+
+impl Manifest {
+    pub fn load(output_dir: &Path) -> Result<Self, Crawler> {
+        let path = output_dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let json = fs::read_to_string(&path)?;
+        serde_json::from_str(&json)
+            .map_err(|e| Crawler::Parsing(format!("Invalid {}: {}", path.display(), e)))
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<(), Crawler> {
+        let json =
+            serde_json::to_string_pretty(self).map_err(|e| Crawler::Parsing(e.to_string()))?;
+        fs::write(output_dir.join(MANIFEST_FILE), json)?;
+        Ok(())
+    }
+
+    pub fn by_url(&self) -> HashMap<String, PageRecord> {
+        self.pages
+            .iter()
+            .map(|page| (page.url.clone(), page.clone()))
+            .collect()
+    }
+}
+
+/// How this crawl's pages compare with the previous crawl's manifest.
+#[derive(Debug, Default, PartialEq)]
+pub struct CrawlSummary {
+    pub added: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+}
+
+impl CrawlSummary {
+    pub fn compare(previous: &HashMap<String, PageRecord>, pages: &[PageRecord]) -> Self {
+        let mut summary = CrawlSummary::default();
+        for page in pages {
+            match previous.get(&page.url) {
+                None => summary.added += 1,
+                Some(old) if old.content_hash == page.content_hash => summary.unchanged += 1,
+                Some(_) => summary.changed += 1,
+            }
+        }
+        let current: HashSet<&str> = pages.iter().map(|page| page.url.as_str()).collect();
+        summary.removed = previous
+            .keys()
+            .filter(|url| !current.contains(url.as_str()))
+            .count();
+        summary
+    }
+}
+
+impl std::fmt::Display for CrawlSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} changed, {} unchanged, {} removed",
+            self.added, self.changed, self.unchanged, self.removed
+        )
+    }
+}
+
+pub fn content_hash(content: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn cache_path(output_dir: &Path, hash: &str) -> PathBuf {
+    output_dir.join(PAGE_CACHE_DIR).join(format!("{}.md", hash))
+}
+
+/// Stores extracted markdown in the page cache.
+pub fn cache_page(output_dir: &Path, hash: &str, content: &str) -> Result<(), Crawler> {
+    let path = cache_path(output_dir, hash);
+    if !path.exists() {
+        fs::create_dir_all(output_dir.join(PAGE_CACHE_DIR))?;
+        fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+/// Reads markdown back from the page cache.
+pub fn cached_page(output_dir: &Path, hash: &str) -> Option<String> {
+    fs::read_to_string(cache_path(output_dir, hash)).ok()
+}
+
+/// Deletes cached markdown that none of `pages` refers to any more.
+pub fn prune_cache(output_dir: &Path, pages: &[PageRecord]) -> Result<(), Crawler> {
+    let dir = output_dir.join(PAGE_CACHE_DIR);
+    if !dir.exists() {
+        return Ok(());
+    }
+    let live: HashSet<String> = pages
+        .iter()
+        .map(|page| format!("{}.md", page.content_hash))
+        .collect();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !live.contains(entry.file_name().to_string_lossy().as_ref()) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = std::env::temp_dir().join(format!("crawl-state-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut frontier = Frontier::new("https://example.com/");
+        frontier.push("https://example.com/a".to_string(), 1);
+        let mut state = CrawlState::new("https://example.com/", frontier);
+        state.fetched = 1;
+        state.pages.push(PageRecord {
+            url: "https://example.com/".to_string(),
+            title: "Home".to_string(),
+            path: "Home.md".to_string(),
+            depth: 0,
+            content_hash: content_hash("# Home"),
+            validators: Validators::default(),
+            links: Vec::new(),
+            anchors: BTreeMap::new(),
+        });
+        state.save(&dir).unwrap();
+
+        let mut loaded = CrawlState::load(&dir).unwrap().unwrap();
+        assert_eq!(loaded.start_url, "https://example.com/");
+        assert_eq!(loaded.fetched, 1);
+        assert_eq!(loaded.pages, state.pages);
+        assert_eq!(
+            loaded.frontier.pop(),
+            Some(("https://example.com/".to_string(), 0))
+        );
+        assert!(!loaded.frontier.push("https://example.com/a".to_string(), 1));
+
+        CrawlState::remove(&dir).unwrap();
+        assert!(CrawlState::load(&dir).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn record(url: &str, content: &str) -> PageRecord {
+        PageRecord {
+            url: url.to_string(),
+            title: String::new(),
+            path: String::new(),
+            depth: 0,
+            content_hash: content_hash(content),
+            validators: Validators::default(),
+            links: Vec::new(),
+            anchors: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_crawl_summary() {
+        let previous = Manifest {
+            pages: vec![
+                record("https://example.com/a", "a"),
+                record("https://example.com/b", "b"),
+                record("https://example.com/c", "c"),
+            ],
+        };
+        let pages = vec![
+            record("https://example.com/a", "a"),
+            record("https://example.com/b", "b, edited"),
+            record("https://example.com/d", "d"),
+        ];
+
+        assert_eq!(
+            CrawlSummary::compare(&previous.by_url(), &pages),
+            CrawlSummary {
+                added: 1,
+                changed: 1,
+                unchanged: 1,
+                removed: 1,
+            }
+        );
+    }
+}
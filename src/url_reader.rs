@@ -1,15 +1,17 @@
+use crate::canonical;
+use crate::fetcher::{Conditional, Fetcher, Validators};
+use crate::images;
+use crate::links;
+use crate::markdown::Converter;
+use crate::profile::Profile;
 use base64::prelude::*;
-use fantoccini::ClientBuilder;
-use regex::Regex;
 use scraper::{ElementRef, Html, Selector};
 use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
-use std::process::{Child, Command};
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
-use std::thread;
 use std::time::Duration;
-use tokio::runtime::Runtime;
 use url::Url;
 
 const MAX_IMAGE_SIZE: usize = 10 * 1024 * 1024; // 10MB
@@ -18,6 +20,11 @@ static OUTPUT_DIR: OnceLock<PathBuf> = OnceLock::new();
 #[derive(Debug)]
 pub enum Crawler {
     Network(String),
+    Http {
+        url: String,
+        status: u16,
+        retry_after: Option<Duration>,
+    },
     Parsing(String),
     Browser(String),
     Io(std::io::Error),
@@ -29,6 +36,7 @@ impl std::fmt::Display for Crawler {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Crawler::Network(msg) => write!(f, "Network error: {}", msg),
+            Crawler::Http { url, status, .. } => write!(f, "HTTP error {} for {}", status, url),
             Crawler::Parsing(msg) => write!(f, "Parsing error: {}", msg),
             Crawler::Browser(msg) => write!(f, "Browser error: {}", msg),
             Crawler::Io(err) => write!(f, "IO error: {}", err),
@@ -45,6 +53,12 @@ impl From<std::io::Error> for Crawler {
 pub struct PageContent {
     pub title: String,
     pub content: String,
+    pub links: Vec<String>,
+    pub validators: Validators,
+    /// Absolute URL from the page's `<link rel="canonical">`
+    pub canonical: Option<String>,
+    /// Heading ids mapped to their markdown slugs
+    pub anchors: BTreeMap<String, String>,
 }
 
 fn get_element_text(element: &ElementRef) -> String {
@@ -87,153 +101,68 @@ fn normalize_url(url: &str, base_url: &str) -> String {
     }
 }
 
-struct GeckoDriver {
-    process: Child,
-}
-
-impl GeckoDriver {
-    fn new() -> Result<Self, Crawler> {
-        // First, try to kill any existing GeckoDriver processes
-        let _ = Command::new("pkill").args(["-f", "geckodriver"]).output();
-
-        // Wait a moment for the process to be cleaned up
-        thread::sleep(Duration::from_millis(500));
-
-        println!("Starting GeckoDriver...");
-        let process = Command::new("geckodriver")
-            .arg("--port")
-            .arg("4444")
-            .spawn()
-            .map_err(|e| Crawler::Browser(e.to_string()))?;
-
-        // Wait for the driver to start
-        thread::sleep(Duration::from_secs(1));
-
-        Ok(GeckoDriver { process })
-    }
-
-    fn cleanup(&mut self) {
-        println!("Stopping GeckoDriver...");
-        let _ = self.process.kill();
-        let _ = Command::new("pkill").args(["-f", "geckodriver"]).output();
-    }
-}
-
-impl Drop for GeckoDriver {
-    fn drop(&mut self) {
-        self.cleanup();
-    }
+/// Fetches `url` through `fetcher` and extracts its content and links the way
+/// `profile` describes.
+///
+/// Returns `None` when the server reports that the page still matches `known`.
+pub async fn fetch_page(
+    fetcher: &dyn Fetcher,
+    url: &str,
+    known: &Validators,
+    profile: &Profile,
+) -> Result<Option<PageContent>, Crawler> {
+    let (html, validators) = match fetcher.fetch_html_if_modified(url, known).await? {
+        Conditional::Modified { html, validators } => (html, validators),
+        Conditional::NotModified => return Ok(None),
+    };
+    let mut content = extract_content(&html, url, fetcher, profile).await?;
+    content.links = extract_links(&html, url, profile);
+    content.validators = validators;
+    content.canonical = canonical::declared_canonical(&html, url);
+    Ok(Some(content))
 }
 
-pub async fn fetch_url_with_firefox(url: &str) -> Result<PageContent, Crawler> {
-    let rt = Runtime::new().map_err(Crawler::Io)?;
-
-    rt.block_on(async {
-        let mut driver = None;
-        let mut last_error = None;
-        let mut retries = 0;
-        let max_retries = 3;
+async fn extract_content(
+    html: &str,
+    base_url: &str,
+    fetcher: &dyn Fetcher,
+    profile: &Profile,
+) -> Result<PageContent, Crawler> {
+    let mut document = Html::parse_document(html);
 
-        while retries < max_retries {
-            match GeckoDriver::new() {
-                Ok(d) => {
-                    driver = Some(d);
-                    break;
-                }
-                Err(e) => {
-                    last_error = Some(e);
-                    retries += 1;
-                    thread::sleep(Duration::from_secs(1));
-                }
-            }
-        }
-
-        if let Some(mut driver) = driver {
-            // Create capabilities using serde_json's Map
-            let mut caps = serde_json::Map::new();
-            let mut firefox_opts = serde_json::Map::new();
-            firefox_opts.insert(
-                "args".to_string(),
-                serde_json::Value::Array(vec![serde_json::Value::String("--headless".to_string())]),
-            );
-            caps.insert(
-                "moz:firefoxOptions".to_string(),
-                serde_json::Value::Object(firefox_opts),
-            );
-
-            let client = match ClientBuilder::native()
-                .capabilities(caps)
-                .connect("http://localhost:4444")
-                .await
-            {
-                Ok(c) => c,
-                Err(e) => {
-                    return Err(Crawler::Browser(format!(
-                        "Failed to connect to WebDriver: {}",
-                        e
-                    )));
-                }
-            };
-
-            match client.goto(url).await {
-                Ok(_) => {
-                    thread::sleep(Duration::from_secs(2));
-
-                    match client.source().await {
-                        Ok(html) => {
-                            let content = extract_content(&html, url).await?;
-                            driver.cleanup();
-                            Ok(content)
-                        }
-                        Err(e) => Err(Crawler::Browser(format!(
-                            "Failed to get page source: {}",
-                            e
-                        ))),
-                    }
+    // Drop the page chrome the profile knows about before anything is read
+    for selector_str in &profile.remove {
+        if let Ok(selector) = Selector::parse(selector_str) {
+            let removed: Vec<_> = document
+                .select(&selector)
+                .map(|element| element.id())
+                .collect();
+            for id in removed {
+                if let Some(mut node) = document.tree.get_mut(id) {
+                    node.detach();
                 }
-                Err(e) => Err(Crawler::Browser(format!(
-                    "Failed to navigate to URL: {}",
-                    e
-                ))),
             }
-        } else {
-            Err(Crawler::Browser(format!(
-                "Failed to connect to WebDriver after retries: {:?}",
-                last_error
-            )))
         }
-    })
-}
-
-async fn extract_content(html: &str, base_url: &str) -> Result<PageContent, Crawler> {
-    let document = Html::parse_document(html);
-
-    // Try multiple selectors for GitBook content
-    let content_selectors = [
-        "div[role='main']",
-        "main",
-        "div.content",
-        "div.markdown",
-        "div.page-inner",
-        "div#content",
-        "div.documentation",
-        "article",
-        "div[class*='content']",  // Match any class containing 'content'
-        "div[class*='markdown']", // Match any class containing 'markdown'
-    ];
+    }
 
     let mut content = String::new();
     let mut title = String::new();
+    let mut converted = Vec::new();
 
     // Extract title
-    if let Ok(title_selector) = Selector::parse("h1") {
-        if let Some(title_element) = document.select(&title_selector).next() {
-            title = get_element_text(&title_element);
+    for selector_str in &profile.title {
+        if let Ok(title_selector) = Selector::parse(selector_str) {
+            if let Some(title_element) = document.select(&title_selector).next() {
+                title = get_element_text(&title_element);
+            }
+        }
+        if !title.is_empty() {
+            break;
         }
     }
 
     // Try each content selector until we find content
-    for selector_str in content_selectors {
+    for selector_str in &profile.content {
         if let Ok(selector) = Selector::parse(selector_str) {
             for content_element in document.select(&selector) {
                 // Skip navigation elements
@@ -251,65 +180,13 @@ async fn extract_content(html: &str, base_url: &str) -> Result<PageContent, Craw
                     }
                 }
 
-                // Pre-process HTML before conversion
-                let mut html_content = content_element.html();
-
-                // Convert <details> and <summary> to markdown
-                html_content = html_content
-                    .replace("<details>", "\n\n<details>\n")
-                    .replace("</details>", "\n</details>\n\n")
-                    .replace("<summary>", "\n### ")
-                    .replace("</summary>", "\n");
-
-                // Remove HTML classes and styles
-                let re = Regex::new(r#"class="[^"]*""#).unwrap();
-                html_content = re.replace_all(&html_content, "").to_string();
-
-                let re = Regex::new(r#"style="[^"]*""#).unwrap();
-                html_content = re.replace_all(&html_content, "").to_string();
-
-                // Convert HTML to markdown first
-                let mut element_content = html2md::parse_html(&html_content);
-
-                // Find and process all image tags using regex
-                let img_regex = Regex::new(r#"<img[^>]*src=["']([^"']+)["'][^>]*alt=["']([^"']*)["'][^>]*>|<img[^>]*src=["']([^"']+)["'][^>]*>"#).unwrap();
-
-                // First pass: HTML images
-                for cap in img_regex.captures_iter(&html_content) {
-                    let src = cap.get(1).or_else(|| cap.get(3)).map_or("", |m| m.as_str());
-                    let alt = cap.get(2).map_or("", |m| m.as_str());
-
-                    if let Some(downloaded_path) = download_image(src, base_url).await {
-                        // Replace both the HTML image tag and any markdown version that might exist
-                        let img_md = format!("![{}]({})", alt, downloaded_path);
-                        element_content =
-                            element_content.replace(&format!("![{}]({})", alt, src), &img_md);
-                        element_content = element_content.replace(src, &downloaded_path);
-                    }
-                }
-
-                // Second pass: Find markdown-style images and collect replacements
-                let md_img_regex = Regex::new(r"!\[[^\]]*\]\(([^)]+)\)").unwrap();
-                let mut replacements = Vec::new();
-
-                for cap in md_img_regex.captures_iter(&element_content) {
-                    if let Some(src_match) = cap.get(1) {
-                        let src = src_match.as_str();
-                        if let Some(downloaded_path) = download_image(src, base_url).await {
-                            replacements
-                                .push((format!("({})", src), format!("({})", downloaded_path)));
-                        }
-                    }
-                }
-
-                // Apply collected replacements
-                for (from, to) in replacements {
-                    element_content = element_content.replace(&from, &to);
-                }
+                let element_content =
+                    convert_element(&content_element, base_url, fetcher, profile).await;
 
                 if !element_content.trim().is_empty() {
                     content.push_str(&element_content);
                     content.push_str("\n\n");
+                    converted.push(content_element);
                 }
             }
             if !content.is_empty() {
@@ -322,165 +199,120 @@ async fn extract_content(html: &str, base_url: &str) -> Result<PageContent, Craw
     if content.is_empty() {
         if let Ok(body_selector) = Selector::parse("body") {
             if let Some(body_element) = document.select(&body_selector).next() {
-                content = html2md::parse_html(&body_element.html());
+                content = convert_element(&body_element, base_url, fetcher, profile).await;
+                converted.push(body_element);
             }
         }
     }
 
-    // Process links
-    if let Ok(link_selector) = Selector::parse("a") {
-        for link in document.select(&link_selector) {
-            if let Some(href) = link.value().attr("href") {
-                let relative_path = convert_to_relative_path(href, base_url);
-                content = content.replace(href, &relative_path);
-            }
-        }
-    }
-
-    // Clean up the content
-    content = content
-        .lines()
-        .filter(|line| {
-            let line = line.trim();
-            !line.is_empty()
-                && !line.contains("Last updated")
-                && !line.contains("Previous")
-                && !line.contains("Next")
-                && !line.contains("Table of contents")
-                && !line.contains("In this article")
-                && !line.contains("On this page")
-                && !line.contains("Contents")
-                && !line.contains(">")  // Remove empty blockquotes
-                && !line.contains("==========")  // Remove header underlines
-                && !line.contains("----------") // Remove subheader underlines
-        })
-        .collect::<Vec<_>>()
-        .join("\n\n");
-
-    // Additional cleanup
-    content = content
-        .replace(r#"<div>"#, "")
-        .replace(r#"</div>"#, "")
-        .replace(r#"<span>"#, "")
-        .replace(r#"</span>"#, "")
-        .replace(r#"<p>"#, "")
-        .replace(r#"</p>"#, "\n\n")
-        .replace("&nbsp;", " ")
-        .replace("&quot;", "\"")
-        .replace("&lt;", "<")
-        .replace("&gt;", ">")
-        .replace("&amp;", "&")
-        .replace("\n\n###\n\n", "\n\n### ") // Fix heading formatting
-        .replace("\n\n##\n\n", "\n\n## ")
-        .replace("\n\n#\n\n", "\n\n# ")
-        .replace(" ###", "###") // Remove extra spaces before headings
-        .replace(" ##", "##")
-        .replace(" #", "#");
-
-    // Remove multiple newlines
-    let re = Regex::new(r"\n{3,}").unwrap();
-    content = re.replace_all(&content, "\n\n").to_string();
-
-    // Remove empty headings
-    let re = Regex::new(r"###\s*\n").unwrap();
-    content = re.replace_all(&content, "").to_string();
-
-    // Remove repeated link references
-    let re = Regex::new(r"\[.*?\]\(.*?\)\s*\[.*?\]\(.*?\)").unwrap();
-    content = re.replace_all(&content, "").to_string();
-
-    // Fix code block formatting
-    let re = Regex::new(r"```\s*\n\s*```").unwrap();
-    content = re.replace_all(&content, "").to_string();
-
-    // Fix multiple spaces
-    let re = Regex::new(r" {2,}").unwrap();
-    content = re.replace_all(&content, " ").to_string();
-
-    // Fix heading spacing
-    let re = Regex::new(r"\n\n(#{1,6})\s+").unwrap();
-    content = re.replace_all(&content, "\n\n$1 ").to_string();
-
     Ok(PageContent {
         title: if title.is_empty() {
             "Untitled".to_string()
         } else {
             title
         },
-        content,
+        content: profile.rewrite(&(content.trim_end().to_string() + "\n")),
+        links: Vec::new(),
+        validators: Validators::default(),
+        canonical: None,
+        anchors: links::heading_anchors(&converted),
     })
 }
 
-pub fn extract_links(url: &str) -> Result<Vec<String>, Crawler> {
-    let response = ureq::get(url)
-        .set(
-            "User-Agent",
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36",
-        )
-        .call()
-        .map_err(|e| Crawler::Network(e.to_string()))?;
+/// Converts `element` to markdown, with every image pointing at its downloaded
+/// copy, or at its remote URL when the download fails. Placeholder images
+/// without a real source are dropped. Admonitions are written in the
+/// profile's style.
+async fn convert_element(
+    element: &ElementRef<'_>,
+    base_url: &str,
+    fetcher: &dyn Fetcher,
+    profile: &Profile,
+) -> String {
+    let mut replacements = HashMap::new();
+    for (outer, image) in images::find_images(element, base_url) {
+        let image = match image {
+            Some(mut image) => {
+                if let Some(src) = download_image(&image.src, base_url, fetcher).await {
+                    image.src = src;
+                }
+                Some(image)
+            }
+            None => None,
+        };
+        replacements.insert(outer.id(), image);
+    }
+    Converter::new(base_url, &replacements, profile.admonitions).convert(element)
+}
 
-    let body = response
-        .into_string()
-        .map_err(|e| Crawler::Parsing(e.to_string()))?;
+/// Collects the absolute URLs of the links on a page, starting with those in
+/// the profile's navigation trees so pages are crawled in the site's reading
+/// order. Then come the links inside the profile's `follow` elements, or every
+/// `<a href>` and `<link href>` when it names none.
+///
+/// Fragments are stripped so that `page#section` and `page` resolve to the
+/// same crawl target. Scoping to the crawl root is left to the caller.
+pub fn extract_links(html: &str, page_url: &str, profile: &Profile) -> Vec<String> {
+    let document = Html::parse_document(html);
+    let anchor_selector = Selector::parse("a[href]").unwrap();
+    let link_selector = Selector::parse("link[href]").unwrap();
+
+    let anchors_within = |containers: &[String]| {
+        containers
+            .iter()
+            .filter_map(|container| Selector::parse(container).ok())
+            .flat_map(|container| {
+                document
+                    .select(&container)
+                    .flat_map(|container| container.select(&anchor_selector))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut elements = anchors_within(&profile.nav);
+    if profile.follow.is_empty() {
+        elements.extend(document.select(&anchor_selector));
+        elements.extend(document.select(&link_selector));
+    } else {
+        elements.extend(anchors_within(&profile.follow));
+    }
+
+    let mut links = Vec::new();
 
-    let document = Html::parse_document(&body);
+    for element in elements {
+        if let Some(href) = element.value().attr("href") {
+            if href.starts_with('#')
+                || href.starts_with("mailto:")
+                || href.starts_with("javascript:")
+            {
+                continue;
+            }
 
-    let selectors = [
-        Selector::parse("a[href]").unwrap(),
-        Selector::parse("link[href]").unwrap(),
-    ];
+            let normalized_url = canonical::normalize(&normalize_url(href, page_url));
 
-    let mut links = Vec::new();
-    let base_url_parsed =
-        Url::parse(url).map_err(|_| Crawler::Parsing("Invalid base URL".to_string()))?;
-
-    for selector in &selectors {
-        for element in document.select(selector) {
-            if let Some(href) = element.value().attr("href") {
-                let normalized_url = normalize_url(href, url);
-
-                // Filter out external links and fragment identifiers
-                if normalized_url.starts_with(base_url_parsed.as_str())
-                    && !normalized_url.contains('#')
-                    && !links.contains(&normalized_url)
-                {
-                    links.push(normalized_url);
-                }
+            if !links.contains(&normalized_url) {
+                links.push(normalized_url);
             }
         }
     }
 
-    Ok(links)
+    links
 }
 
-async fn download_image(url: &str, base_url: &str) -> Option<String> {
+async fn download_image(url: &str, base_url: &str, fetcher: &dyn Fetcher) -> Option<String> {
     // Handle base64 encoded images
     if url.starts_with("data:image/") {
         return handle_base64_image(url);
     }
 
-    let client = ClientBuilder::native()
-        .connect("http://localhost:4444")
-        .await
-        .ok()?;
-
-    // Resolve relative URL if needed
-    let absolute_url = if url.starts_with("http") {
-        url.to_string()
-    } else {
-        format!("{}{}", base_url.trim_end_matches('/'), url)
-    };
-
     // Extract original URL if it's a proxy URL
-    let original_url = extract_original_url(&absolute_url);
-    println!("Downloading image from: {}", original_url);
+    let original_url = extract_original_url(&normalize_url(url, base_url));
 
     // Create a unique filename
     let mut hasher = Sha256::new();
     hasher.update(original_url.as_bytes());
     let hash = format!("{:x}", hasher.finalize());
-    let filename = format!("{}.{}", &hash[..16], guess_extension(&original_url));
+    let stem = &hash[..16];
 
     // Create images directory if it doesn't exist
     let images_dir = get_output_dir().join("images");
@@ -488,42 +320,67 @@ async fn download_image(url: &str, base_url: &str) -> Option<String> {
         fs::create_dir_all(&images_dir).ok()?;
     }
 
-    let image_path = images_dir.join(&filename);
-
     // Check if image already exists
-    if image_path.exists() {
-        return Some(format!("images/{}", filename));
+    let url_extension = guess_extension(&original_url);
+    if let Some(existing) = existing_image(&images_dir, stem, &url_extension) {
+        return Some(format!("images/{}", existing));
     }
 
-    // Download image
-    match client.goto(&original_url).await {
-        Ok(_) => {
-            match client.source().await {
-                Ok(content) => {
-                    let _ = client.close().await;
-
-                    // Write content to file
-                    if fs::write(&image_path, content.as_bytes()).is_ok() {
-                        Some(format!("images/{}", filename))
-                    } else {
-                        None
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Failed to get page source for image {}: {}", url, e);
-                    let _ = client.close().await;
-                    None
-                }
+    println!("Downloading image from: {}", original_url);
+    let asset = match fetcher.fetch_asset(&original_url, MAX_IMAGE_SIZE).await {
+        Ok(asset) => asset,
+        Err(e) => {
+            eprintln!("Failed to download image {}: {}", url, e);
+            return None;
+        }
+    };
+
+    // Servers answer missing or protected images with an HTML page
+    if asset
+        .content_type
+        .as_deref()
+        .is_some_and(|t| t.starts_with("text/html"))
+    {
+        eprintln!("Not an image: {} returned an HTML page", original_url);
+        return None;
+    }
+    let extension = if url_extension.is_empty() {
+        match sniff_extension(&asset.bytes, asset.content_type.as_deref()) {
+            Some(extension) => extension.to_string(),
+            None => {
+                eprintln!("Could not tell the image type of {}", original_url);
+                return None;
             }
         }
+    } else {
+        url_extension
+    };
+
+    let filename = format!("{}.{}", stem, extension);
+    match fs::write(images_dir.join(&filename), asset.bytes) {
+        Ok(_) => Some(format!("images/{}", filename)),
         Err(e) => {
-            eprintln!("Failed to navigate to image {}: {}", url, e);
-            let _ = client.close().await;
+            eprintln!("Failed to save image {}: {}", original_url, e);
             None
         }
     }
 }
 
+/// Finds an image already downloaded under `stem`. When the URL gives no
+/// extension, any extension found by sniffing an earlier download is accepted.
+fn existing_image(images_dir: &Path, stem: &str, extension: &str) -> Option<String> {
+    if !extension.is_empty() {
+        let filename = format!("{}.{}", stem, extension);
+        return images_dir.join(&filename).exists().then_some(filename);
+    }
+    let prefix = format!("{}.", stem);
+    fs::read_dir(images_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .find(|name| name.starts_with(&prefix))
+}
+
 fn handle_base64_image(data_url: &str) -> Option<String> {
     let parts: Vec<&str> = data_url.split(',').collect();
     if parts.len() != 2 {
@@ -576,7 +433,15 @@ fn handle_base64_image(data_url: &str) -> Option<String> {
 }
 
 fn guess_extension(url: &str) -> String {
-    if let Some(ext) = url.split('.').last() {
+    // Only the path names a file; a query or fragment may contain dots too
+    let path = Url::parse(url)
+        .map(|parsed| parsed.path().to_string())
+        .unwrap_or_else(|_| url.to_string());
+    if let Some(ext) = path
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.split('.').next_back())
+    {
         match ext.to_lowercase().as_str() {
             "jpg" | "jpeg" | "png" | "gif" | "webp" | "svg" => ext.to_string(),
             "pdf" => "pdf".to_string(),
@@ -587,19 +452,47 @@ fn guess_extension(url: &str) -> String {
     }
 }
 
-fn convert_to_relative_path(href: &str, base_url: &str) -> String {
-    // Remove the base URL from the href
-    let url_without_base = href.replace(base_url, "");
-
-    // Remove leading slashes
-    let path = url_without_base.trim_start_matches('/');
-
-    // Convert HTML to markdown
-    if path.ends_with(".html") {
-        path.replace(".html", ".md")
+/// Picks an image extension from the file's magic bytes, falling back to its
+/// `Content-Type`.
+fn sniff_extension(bytes: &[u8], content_type: Option<&str>) -> Option<&'static str> {
+    let head = &bytes[..bytes.len().min(512)];
+    let text_head = String::from_utf8_lossy(head).to_lowercase();
+    let sniffed = if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if head.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("jpg")
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        Some("webp")
+    } else if head.len() >= 12
+        && &head[4..8] == b"ftyp"
+        && matches!(&head[8..12], b"avif" | b"avis")
+    {
+        Some("avif")
+    } else if head.starts_with(&[0x00, 0x00, 0x01, 0x00]) {
+        Some("ico")
+    } else if head.starts_with(b"BM") {
+        Some("bmp")
+    } else if text_head.trim_start().starts_with("<svg")
+        || (text_head.trim_start().starts_with("<?xml") && text_head.contains("<svg"))
+    {
+        Some("svg")
     } else {
-        path.to_string()
-    }
+        None
+    };
+
+    sniffed.or(match content_type.unwrap_or("") {
+        "image/png" => Some("png"),
+        "image/jpeg" | "image/jpg" => Some("jpg"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        "image/avif" => Some("avif"),
+        "image/svg+xml" => Some("svg"),
+        "image/x-icon" | "image/vnd.microsoft.icon" => Some("ico"),
+        "image/bmp" => Some("bmp"),
+        _ => None,
+    })
 }
 
 pub fn set_output_dir(dir: PathBuf) {
@@ -620,7 +513,7 @@ pub fn extract_original_url(url: &str) -> String {
                     // Convert to direct Imgur URL
                     return format!(
                         "https://i.imgur.com/{}.png",
-                        imgur_path.split('/').last().unwrap_or("")
+                        imgur_path.split('/').next_back().unwrap_or("")
                     );
                 }
             }
@@ -632,6 +525,7 @@ pub fn extract_original_url(url: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::profile::ProfileName;
 
     #[test]
     fn test_normalize_url() {
@@ -649,23 +543,79 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_extract_links() {
+        let generic = ProfileName::Generic.profile().unwrap();
+        let html = r##"
+            <a href="/docs/guide#setup">Guide</a>
+            <a href="intro?utm_source=nav">Intro</a>
+            <a href="/docs/guide">Guide again</a>
+            <a href="#top">Top</a>
+            <a href="mailto:team@example.com">Mail</a>
+        "##;
+        assert_eq!(
+            extract_links(html, "https://example.com/docs/", &generic),
+            vec![
+                "https://example.com/docs/guide".to_string(),
+                "https://example.com/docs/intro".to_string(),
+            ]
+        );
+
+        let html = r#"
+            <main><a href="/docs/faq">FAQ</a></main>
+            <footer><a href="/legal">Legal</a></footer>
+            <aside class="sidebar"><a href="/docs/intro">Intro</a><a href="/docs/faq">FAQ</a></aside>
+        "#;
+        let mut profile = generic.clone();
+        profile.nav = vec!["aside.sidebar".to_string()];
+        assert_eq!(
+            extract_links(html, "https://example.com/docs/", &profile),
+            vec![
+                "https://example.com/docs/intro".to_string(),
+                "https://example.com/docs/faq".to_string(),
+                "https://example.com/legal".to_string(),
+            ]
+        );
+
+        profile.follow = vec!["main".to_string()];
+        assert_eq!(
+            extract_links(html, "https://example.com/docs/", &profile),
+            vec![
+                "https://example.com/docs/intro".to_string(),
+                "https://example.com/docs/faq".to_string(),
+            ]
+        );
+    }
+
     #[test]
     fn test_guess_extension() {
         assert_eq!(guess_extension("image.jpg"), "jpg");
         assert_eq!(guess_extension("file.png"), "png");
         assert_eq!(guess_extension("doc.pdf"), "pdf");
         assert_eq!(guess_extension("noextension"), "");
+        assert_eq!(
+            guess_extension("https://cdn.example.com/logo.png?w=200"),
+            "png"
+        );
+        assert_eq!(guess_extension("https://files.example.com/v1.2/image"), "");
     }
 
     #[test]
-    fn test_convert_to_relative_path() {
+    fn test_sniff_extension() {
+        assert_eq!(sniff_extension(b"\x89PNG\r\n\x1a\n\0\0", None), Some("png"));
         assert_eq!(
-            convert_to_relative_path("https://example.com/docs/guide.html", "https://example.com"),
-            "docs/guide.md"
+            sniff_extension(&[0xff, 0xd8, 0xff, 0xe0], None),
+            Some("jpg")
         );
+        assert_eq!(sniff_extension(b"RIFF\0\0\0\0WEBPVP8 ", None), Some("webp"));
         assert_eq!(
-            convert_to_relative_path("/docs/guide.html", "https://example.com"),
-            "docs/guide.md"
+            sniff_extension(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"\"/>", None),
+            Some("svg")
+        );
+        assert_eq!(sniff_extension(b"????", Some("image/gif")), Some("gif"));
+        assert_eq!(
+            sniff_extension(b"????", Some("application/octet-stream")),
+            None
         );
     }
 }
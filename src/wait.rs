@@ -0,0 +1,92 @@
+use scraper::Selector;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A condition the browser backend waits for after navigating to a page,
+/// before it reads the rendered HTML.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum WaitStrategy {
+    /// `document.readyState` is `complete`
+    Load,
+    /// No new network request has finished for a quiet period
+    NetworkIdle,
+    /// The DOM has not changed for a quiet period
+    DomStable,
+    /// An element matches the CSS selector
+    Selector(String),
+}
+
+/// The conditions a rendered page must meet, checked in order, and how long
+/// the browser waits for them in total before reading the page anyway.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageReady {
+    pub strategies: Vec<WaitStrategy>,
+    pub timeout: Duration,
+}
+
+impl FromStr for WaitStrategy {
+    type Err = String;
+
+    /// Parses `load`, `network-idle`, `dom-stable` or `selector:<css>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "load" => Ok(WaitStrategy::Load),
+            "network-idle" => Ok(WaitStrategy::NetworkIdle),
+            "dom-stable" => Ok(WaitStrategy::DomStable),
+            other => match other.strip_prefix("selector:").map(str::trim) {
+                Some(selector) => {
+                    Selector::parse(selector)
+                        .map_err(|e| format!("invalid selector {:?}: {:?}", selector, e))?;
+                    Ok(WaitStrategy::Selector(selector.to_string()))
+                }
+                None => Err(format!(
+                    "unknown wait strategy {:?}; expected load, network-idle, dom-stable or selector:<css>",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for WaitStrategy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for WaitStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitStrategy::Load => write!(f, "load"),
+            WaitStrategy::NetworkIdle => write!(f, "network-idle"),
+            WaitStrategy::DomStable => write!(f, "dom-stable"),
+            WaitStrategy::Selector(selector) => write!(f, "selector:{}", selector),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_wait_strategy() {
+        assert_eq!("load".parse(), Ok(WaitStrategy::Load));
+        assert_eq!("network-idle".parse(), Ok(WaitStrategy::NetworkIdle));
+        assert_eq!(
+            "selector: main h1, .content".parse(),
+            Ok(WaitStrategy::Selector("main h1, .content".to_string()))
+        );
+        assert_eq!(
+            WaitStrategy::Selector(".vp-doc".to_string()).to_string(),
+            "selector:.vp-doc"
+        );
+        assert!("sleep".parse::<WaitStrategy>().is_err());
+        assert!("selector:div > > p".parse::<WaitStrategy>().is_err());
+    }
+}